@@ -1,23 +1,28 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 use syn::{parse2, ItemFn};
+use crate::returns_result;
 
 pub fn client_impl(item: TokenStream) -> TokenStream {
-    println!("{}", item.to_string());
-
     let function = parse2::<ItemFn>(item.clone()).unwrap();
     let name = function.sig.ident.clone();
 
     assert_ne!(name.to_string(), "main");
     assert_eq!(function.sig.inputs.len(), 1);
 
+    let call = if returns_result(&function) {
+        quote!(#name(&mut _client)?;)
+    } else {
+        quote!(#name(&mut _client);)
+    };
+
     quote! {
         #item
 
-        pub fn main() {
+        pub fn main() -> std::io::Result<()> {
             let mut _client: Client<ProtocolState, Packets> = Client::new();
-            #name(&mut _client);
-            _client.event_loop();
+            #call
+            _client.event_loop()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote};
+use syn::{parse2, Data, DeriveInput, Error, Field, Fields, GenericParam, Generics, Member, Type};
+
+enum VarIntField {
+    Signed,
+    /// Sent through `u64`, so values above `i64::MAX` go out negative and come back intact.
+    Unsigned,
+}
+
+fn varint_field(field: &Field) -> syn::Result<Option<VarIntField>> {
+    if !is_varint(field)? {
+        return Ok(None);
+    }
+    let name = match &field.ty {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident().map(ToString::to_string),
+        _ => None,
+    };
+    match name.as_deref() {
+        Some("i8" | "i16" | "i32" | "i64" | "isize") => Ok(Some(VarIntField::Signed)),
+        Some("u8" | "u16" | "u32" | "u64" | "usize") => Ok(Some(VarIntField::Unsigned)),
+        _ => Err(Error::new_spanned(&field.ty, "`varint` fields must be an integer type of at most 64 bits")),
+    }
+}
+
+fn is_varint(field: &Field) -> syn::Result<bool> {
+    let mut varint = false;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("dragonet")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("varint") {
+                varint = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown dragonet attribute"))
+            }
+        })?;
+    }
+    Ok(varint)
+}
+
+fn add_bounds(mut generics: Generics, bound: TokenStream) -> Generics {
+    for param in &mut generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse2(bound.clone()).unwrap());
+        }
+    }
+    generics
+}
+
+fn encode_field(field: &Field, value: TokenStream) -> syn::Result<TokenStream> {
+    Ok(match varint_field(field)? {
+        Some(VarIntField::Signed) => quote!(buf.write_var_int(*#value as i64);),
+        Some(VarIntField::Unsigned) => quote!(buf.write_var_int(*#value as u64 as i64);),
+        None => quote!(::dragonet::buffer::Encode::encode(#value, buf);),
+    })
+}
+
+fn decode_field(field: &Field) -> syn::Result<TokenStream> {
+    let ty = &field.ty;
+    Ok(match varint_field(field)? {
+        Some(VarIntField::Signed) => quote!({
+            let value = buf.read_var_int()?;
+            <#ty as ::core::convert::TryFrom<i64>>::try_from(value)
+                .map_err(|_| ::dragonet::buffer::BufferError::OutOfRange(value))?
+        }),
+        Some(VarIntField::Unsigned) => quote!({
+            let value = buf.read_var_int()?;
+            <#ty as ::core::convert::TryFrom<u64>>::try_from(value as u64)
+                .map_err(|_| ::dragonet::buffer::BufferError::OutOfRange(value))?
+        }),
+        None => quote!(<#ty as ::dragonet::buffer::Decode>::decode(buf)?),
+    })
+}
+
+fn bindings(fields: &Fields) -> Vec<Ident> {
+    fields.iter().enumerate()
+        .map(|(index, field)| field.ident.clone().unwrap_or_else(|| format_ident!("field_{}", index)))
+        .collect()
+}
+
+fn pattern(path: TokenStream, fields: &Fields, bindings: &[Ident]) -> TokenStream {
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => quote!(#path),
+    }
+}
+
+fn construct(path: TokenStream, fields: &Fields) -> syn::Result<TokenStream> {
+    let reads = fields.iter().map(decode_field).collect::<syn::Result<Vec<_>>>()?;
+    Ok(match fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|field| &field.ident);
+            quote!(#path { #(#names: #reads),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#reads),*)),
+        Fields::Unit => quote!(#path),
+    })
+}
+
+pub fn encode_derive(input: TokenStream) -> TokenStream {
+    match parse2::<DeriveInput>(input).and_then(expand_encode) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+pub fn decode_derive(input: TokenStream) -> TokenStream {
+    match parse2::<DeriveInput>(input).and_then(expand_decode) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn expand_encode(input: DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let generics = add_bounds(input.generics.clone(), quote!(::dragonet::buffer::Encode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let writes = data.fields.iter().enumerate()
+                .map(|(index, field)| {
+                    let member = field.ident.clone()
+                        .map(Member::Named)
+                        .unwrap_or_else(|| Member::Unnamed(index.into()));
+                    encode_field(field, quote!(&self.#member))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote!(#(#writes)*)
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate()
+                .map(|(index, variant)| {
+                    let variant_name = &variant.ident;
+                    let bindings = bindings(&variant.fields);
+                    let pattern = pattern(quote!(Self::#variant_name), &variant.fields, &bindings);
+                    let writes = variant.fields.iter().zip(&bindings)
+                        .map(|(field, binding)| encode_field(field, quote!(#binding)))
+                        .collect::<syn::Result<Vec<_>>>()?;
+                    let index = index as i64;
+                    Ok(quote! {
+                        #pattern => {
+                            buf.write_var_int(#index);
+                            #(#writes)*
+                        }
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(&input.ident, "unions cannot derive Encode")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::dragonet::buffer::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, buf: &mut ::dragonet::buffer::Buffer) {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_decode(input: DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let generics = add_bounds(input.generics.clone(), quote!(::dragonet::buffer::Decode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let construct = construct(quote!(Self), &data.fields)?;
+            quote!(Ok(#construct))
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate()
+                .map(|(index, variant)| {
+                    let variant_name = &variant.ident;
+                    let construct = construct(quote!(Self::#variant_name), &variant.fields)?;
+                    let index = index as i64;
+                    Ok(quote!(#index => Ok(#construct),))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match buf.read_var_int()? {
+                    #(#arms)*
+                    variant => Err(::dragonet::buffer::BufferError::InvalidVariant(variant)),
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(&input.ident, "unions cannot derive Decode")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::dragonet::buffer::Decode for #name #ty_generics #where_clause {
+            fn decode(buf: &mut ::dragonet::buffer::Buffer) -> Result<Self, ::dragonet::buffer::BufferError> {
+                #body
+            }
+        }
+    })
+}
@@ -1,16 +1,46 @@
 mod server;
 mod client;
+mod codec;
+mod protocol;
 
 use proc_macro::{TokenStream};
+use syn::{ItemFn, ReturnType, Type};
 use crate::client::client_impl;
+use crate::codec::{decode_derive, encode_derive};
+use crate::protocol::protocol_impl;
 use crate::server::server_impl;
 
+fn returns_result(function: &ItemFn) -> bool {
+    let ReturnType::Type(_, ty) = &function.sig.output else {
+        return false;
+    };
+    match ty.as_ref() {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| segment.ident == "Result"),
+        _ => false,
+    }
+}
+
 #[proc_macro_attribute]
-pub fn client(attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn client(_attr: TokenStream, item: TokenStream) -> TokenStream {
     client_impl(item.into()).into()
 }
 
 #[proc_macro_attribute]
-pub fn server(attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn server(_attr: TokenStream, item: TokenStream) -> TokenStream {
     server_impl(item.into()).into()
+}
+
+#[proc_macro]
+pub fn protocol(input: TokenStream) -> TokenStream {
+    protocol_impl(input.into()).into()
+}
+
+#[proc_macro_derive(Encode, attributes(dragonet))]
+pub fn encode(input: TokenStream) -> TokenStream {
+    encode_derive(input.into()).into()
+}
+
+#[proc_macro_derive(Decode, attributes(dragonet))]
+pub fn decode(input: TokenStream) -> TokenStream {
+    decode_derive(input.into()).into()
 }
\ No newline at end of file
@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, parenthesized, parse2, Error, Token, Type};
+
+struct ProtocolDef {
+    states: Vec<StateDef>,
+}
+
+struct StateDef {
+    name: Ident,
+    packets: Vec<PacketDef>,
+}
+
+struct PacketDef {
+    clientbound: bool,
+    name: Ident,
+    fields: Vec<Type>,
+    response: Option<Ident>,
+}
+
+fn expect_keyword(input: ParseStream, keyword: &str) -> syn::Result<Ident> {
+    let ident: Ident = input.parse()?;
+    if ident != keyword {
+        return Err(Error::new(ident.span(), format!("expected `{}`", keyword)));
+    }
+    Ok(ident)
+}
+
+impl Parse for ProtocolDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut states: Vec<StateDef> = Vec::new();
+        while !input.is_empty() {
+            states.push(input.parse()?);
+        }
+        if states.is_empty() {
+            return Err(input.error("a protocol needs at least one state"));
+        }
+        if let Some(state) = states.get(usize::from(u8::MAX) + 1) {
+            return Err(Error::new(state.name.span(), "a protocol can have at most 256 states"));
+        }
+        Ok(ProtocolDef { states })
+    }
+}
+
+impl Parse for StateDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        expect_keyword(input, "state")?;
+        let name = input.parse()?;
+
+        let content;
+        braced!(content in input);
+        let mut packets = Vec::new();
+        while !content.is_empty() {
+            packets.push(content.parse()?);
+        }
+        Ok(StateDef { name, packets })
+    }
+}
+
+impl Parse for PacketDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let direction: Ident = input.parse()?;
+        let clientbound = if direction == "clientbound" {
+            true
+        } else if direction == "serverbound" {
+            false
+        } else {
+            return Err(Error::new(direction.span(), "expected `clientbound` or `serverbound`"));
+        };
+        expect_keyword(input, "packet")?;
+        let name = input.parse()?;
+
+        let mut fields = Vec::new();
+        if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            fields = content.parse_terminated(Type::parse, Token![,])?
+                .into_iter()
+                .collect();
+        }
+
+        let mut response = None;
+        if input.peek(Token![->]) {
+            let arrow = input.parse::<Token![->]>()?;
+            if clientbound {
+                return Err(Error::new(arrow.spans[0], "only serverbound packets can declare a response"));
+            }
+            response = Some(input.parse()?);
+        }
+        input.parse::<Token![;]>()?;
+        Ok(PacketDef { clientbound, name, fields, response })
+    }
+}
+
+pub fn protocol_impl(input: TokenStream) -> TokenStream {
+    match parse2::<ProtocolDef>(input) {
+        Ok(def) => expand(&def),
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn expand(def: &ProtocolDef) -> TokenStream {
+    let mut declared = HashSet::new();
+    for packet in def.states.iter().flat_map(|state| &state.packets) {
+        if !declared.insert((packet.clientbound, packet.name.to_string())) {
+            let direction = if packet.clientbound { "clientbound" } else { "serverbound" };
+            return Error::new(packet.name.span(), format!("{} packet `{}` is declared more than once", direction, packet.name))
+                .to_compile_error();
+        }
+    }
+
+    for packet in def.states.iter().flat_map(|state| &state.packets) {
+        let Some(response) = &packet.response else {
+            continue;
+        };
+        let declared = def.states.iter()
+            .flat_map(|state| &state.packets)
+            .any(|candidate| candidate.clientbound && candidate.name == *response);
+        if !declared {
+            return Error::new(response.span(), format!("no clientbound packet named `{}`", response))
+                .to_compile_error();
+        }
+    }
+
+    let state_names: Vec<&Ident> = def.states.iter().map(|state| &state.name).collect();
+    let state_ids = (0..=u8::MAX).take(state_names.len());
+    let first_state = state_names[0];
+
+    let mut variants = Vec::new();
+    let mut encode_arms = Vec::new();
+    let mut decode_arms = Vec::new();
+    let mut metadata_arms = Vec::new();
+    let mut markers = Vec::new();
+
+    for state in &def.states {
+        let state_name = &state.name;
+        let mut next_clientbound = 0u32;
+        let mut next_serverbound = 0u32;
+
+        for packet in &state.packets {
+            let (variant, direction, id) = if packet.clientbound {
+                next_clientbound += 1;
+                (format_ident!("S2C{}", packet.name), quote!(Clientbound), next_clientbound - 1)
+            } else {
+                next_serverbound += 1;
+                (format_ident!("C2S{}", packet.name), quote!(Serverbound), next_serverbound - 1)
+            };
+
+            let fields = &packet.fields;
+            let bindings: Vec<Ident> = (0..fields.len()).map(|index| format_ident!("field_{}", index)).collect();
+            let mut writes = Vec::new();
+            let mut reads = Vec::new();
+            for (field, binding) in fields.iter().zip(&bindings) {
+                writes.push(quote!(::dragonet::buffer::Encode::encode(#binding, &mut buf);));
+                reads.push(quote!(<#field as ::dragonet::buffer::Decode>::decode(buf)?));
+            }
+
+            let pattern = if fields.is_empty() {
+                quote!(Packets::#variant)
+            } else {
+                quote!(Packets::#variant(#(#bindings),*))
+            };
+            let construct = if fields.is_empty() {
+                quote!(Packets::#variant)
+            } else {
+                quote!(Packets::#variant(#(#reads),*))
+            };
+
+            if fields.is_empty() {
+                variants.push(quote!(#variant));
+            } else {
+                variants.push(quote!(#variant(#(#fields),*)));
+            }
+            encode_arms.push(quote! {
+                #pattern => {
+                    buf.write_var_int(#id as i64);
+                    #(#writes)*
+                }
+            });
+            decode_arms.push(quote! {
+                (ProtocolState::#state_name, ::dragonet::protocol::PacketDirection::#direction, #id) => Ok(#construct),
+            });
+            let (payload, owned, values) = match fields.len() {
+                0 => (quote!(()), quote!(()), quote!(())),
+                1 => (quote!(&'a #(#fields)*), quote!(#(#fields)*), quote!(field_0)),
+                _ => (quote!((#(&'a #fields),*)), quote!((#(#fields),*)), quote!((#(#bindings),*))),
+            };
+            markers.push(quote! {
+                #[derive(Clone, Copy, Debug)]
+                pub struct #variant;
+
+                impl ::dragonet::protocol::PacketKind<ProtocolState, Packets> for #variant {
+                    type Payload<'a> = #payload;
+                    type Owned = #owned;
+
+                    fn payload(packet: &Packets) -> Option<Self::Payload<'_>> {
+                        #[allow(unreachable_patterns)]
+                        match packet {
+                            #pattern => Some(#values),
+                            _ => None,
+                        }
+                    }
+
+                    fn into_owned(packet: Packets) -> Result<Self::Owned, Packets> {
+                        #[allow(unreachable_patterns)]
+                        match packet {
+                            #pattern => Ok(#values),
+                            packet => Err(packet),
+                        }
+                    }
+
+                    fn from_owned(#values: Self::Owned) -> Packets {
+                        #pattern
+                    }
+                }
+            });
+            if let Some(response) = &packet.response {
+                let response = format_ident!("S2C{}", response);
+                markers.push(quote! {
+                    impl ::dragonet::protocol::RequestKind<ProtocolState, Packets> for #variant {
+                        type Response = #response;
+                    }
+                });
+            }
+            metadata_arms.push(quote! {
+                Packets::#variant { .. } => ::dragonet::protocol::PacketMetadata {
+                    id: #id,
+                    state: ProtocolState::#state_name,
+                    direction: ::dragonet::protocol::PacketDirection::#direction,
+                },
+            });
+        }
+    }
+
+    quote! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum ProtocolState {
+            #(#state_names),*
+        }
+
+        impl ::dragonet::protocol::PacketState for ProtocolState {
+            fn get_state_by_id(id: u8) -> Self {
+                match id {
+                    #(#state_ids => ProtocolState::#state_names,)*
+                    _ => ProtocolState::#first_state,
+                }
+            }
+        }
+
+        #[derive(Debug)]
+        pub enum Packets {
+            #(#variants),*
+        }
+
+        impl ::dragonet::protocol::Protocol<ProtocolState> for Packets {
+            fn encode(&self) -> ::dragonet::buffer::Buffer {
+                let mut buf = ::dragonet::buffer::Buffer::new();
+                match self {
+                    #(#encode_arms)*
+                }
+                buf
+            }
+
+            fn decode(
+                buf: &mut ::dragonet::buffer::Buffer,
+                meta: &::dragonet::protocol::PacketMetadata<ProtocolState>,
+            ) -> Result<Self, ::dragonet::protocol::DecodeError> {
+                match (&meta.state, &meta.direction, meta.id) {
+                    #(#decode_arms)*
+                    (_, _, id) => Err(::dragonet::protocol::DecodeError::UnknownPacket(id)),
+                }
+            }
+
+            fn metadata(&self) -> ::dragonet::protocol::PacketMetadata<ProtocolState> {
+                match self {
+                    #(#metadata_arms)*
+                }
+            }
+        }
+
+        pub mod packets {
+            use super::*;
+
+            #(#markers)*
+        }
+    }
+}
@@ -1,22 +1,27 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 use syn::{parse2, ItemFn};
+use crate::returns_result;
 
 pub fn server_impl(item: TokenStream) -> TokenStream {
-    println!("{}", item.to_string());
-
     let function = parse2::<ItemFn>(item.clone()).unwrap();
     let name = function.sig.ident.clone();
 
     assert_ne!(name.to_string(), "main");
 
+    let call = if returns_result(&function) {
+        quote!(#name(&mut _srv)?;)
+    } else {
+        quote!(#name(&mut _srv);)
+    };
+
     quote! {
         #item
 
-        pub fn main() {
+        pub fn main() -> std::io::Result<()> {
             let mut _srv: Server<ProtocolState, Packets> = Server::new();
-            #name(&mut _srv);
-            _srv.event_loop();
+            #call
+            _srv.event_loop()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,189 @@
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+pub enum JoinError {
+    Cancelled,
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("task was cancelled, not panicked"),
+        }
+    }
+}
+
+impl Debug for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "JoinError::Cancelled"),
+            JoinError::Panicked(_) => write!(f, "JoinError::Panicked(..)"),
+        }
+    }
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panicked(_) => write!(f, "task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+struct JoinInner<T> {
+    result: Option<Result<T, JoinError>>,
+    finished: bool,
+    aborted: bool,
+    join_waker: Option<Waker>,
+    task_waker: Option<Waker>,
+}
+
+pub(crate) struct JoinState<T> {
+    inner: Mutex<JoinInner<T>>,
+    condvar: Condvar,
+}
+
+impl<T> JoinState<T> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(JoinState {
+            inner: Mutex::new(JoinInner {
+                result: None,
+                finished: false,
+                aborted: false,
+                join_waker: None,
+                task_waker: None,
+            }),
+            condvar: Condvar::new(),
+        })
+    }
+
+    fn finish(&self, result: Result<T, JoinError>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.result = Some(result);
+        inner.finished = true;
+        inner.task_waker = None;
+        if let Some(waker) = inner.join_waker.take() {
+            waker.wake();
+        }
+        self.condvar.notify_all();
+    }
+}
+
+pub(crate) struct Harness<F: Future> {
+    future: Option<Pin<Box<F>>>,
+    state: Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Harness<F> {
+    pub(crate) fn new(future: F, state: Arc<JoinState<F::Output>>) -> Self {
+        Harness {
+            future: Some(Box::pin(future)),
+            state,
+        }
+    }
+}
+
+impl<F: Future> Future for Harness<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        {
+            let mut inner = self.state.inner.lock().unwrap();
+            if inner.aborted {
+                drop(inner);
+                self.future = None;
+                self.state.finish(Err(JoinError::Cancelled));
+                return Poll::Ready(());
+            }
+            inner.task_waker = Some(cx.waker().clone());
+        }
+
+        let Some(future) = self.future.as_mut() else {
+            return Poll::Ready(());
+        };
+        let result = match std::panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(value)) => Ok(value),
+            Err(payload) => Err(JoinError::Panicked(payload)),
+        };
+        self.future = None;
+        self.state.finish(result);
+        Poll::Ready(())
+    }
+}
+
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(state: Arc<JoinState<T>>) -> Self {
+        JoinHandle { state }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.inner.lock().unwrap().finished
+    }
+
+    pub fn abort(&self) {
+        let mut inner = self.state.inner.lock().unwrap();
+        if inner.finished {
+            return;
+        }
+        inner.aborted = true;
+        if let Some(waker) = inner.task_waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut inner = self.state.inner.lock().unwrap();
+        loop {
+            if let Some(result) = inner.result.take() {
+                return result;
+            }
+            inner = self.state.condvar.wait(inner).unwrap();
+        }
+    }
+}
+
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.state.inner.lock().unwrap();
+        match inner.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                inner.join_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -1,92 +1,183 @@
 mod tests;
+mod join;
+pub mod time;
 
+use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
 use std::future::Future;
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::task::{Context, Poll};
 use futures::task;
-use futures::task::{ArcWake, SpawnExt};
+use futures::task::ArcWake;
+use crate::join::{Harness, JoinState};
+
+pub use crate::join::{JoinError, JoinHandle};
 
 pub struct Runtime {
-    scheduled: Receiver<Arc<Task>>,
-    sender: Sender<Arc<Task>>,
+    shared: Arc<Shared>,
+    workers: usize,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Runtime {
     pub fn new() -> Self {
-        let mpsc = channel();
+        let workers = std::thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1);
+        Runtime::with_workers(workers)
+    }
+
+    pub fn with_workers(workers: usize) -> Self {
         Runtime {
-            scheduled: mpsc.1,
-            sender: mpsc.0
+            shared: Arc::new(Shared {
+                queue: Mutex::new(VecDeque::new()),
+                condvar: Condvar::new(),
+                pending: AtomicUsize::new(0),
+            }),
+            workers: workers.max(1),
         }
     }
 
-    pub fn spawn<F>(&mut self, future: F)
-        where F: Future<Output=()> + Send  + 'static {
-        self.sender.send(Arc::new(Task {
-            data: Mutex::new(TaskFutureData {
-                future: Box::pin(future),
-                poll: Poll::Pending,
-            }),
-            executor: self.sender.clone(),
-        })).expect("failed to send future somehow");
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn spawn<F>(&mut self, future: F) -> JoinHandle<F::Output>
+        where F: Future + Send + 'static, F::Output: Send + 'static {
+        let state = JoinState::new();
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(Harness::new(future, state.clone())))),
+            state: AtomicU8::new(SCHEDULED),
+            shared: self.shared.clone(),
+        });
+        self.shared.schedule(task);
+        JoinHandle::new(state)
     }
 
     pub fn run(&mut self) {
-        while let Ok(task) = self.scheduled.recv() {
-            println!("Task: {:?}", task);
-            std::thread::spawn(move || {
-                task.poll();
-            });
-        }
+        std::thread::scope(|scope| {
+            for _ in 0..self.workers {
+                scope.spawn(|| self.shared.work());
+            }
+        });
     }
 }
 
-#[derive(Debug)]
-struct Task {
-    data: Mutex<TaskFutureData>,
-    executor: Sender<Arc<Task>>,
+struct Shared {
+    queue: Mutex<VecDeque<Arc<Task>>>,
+    condvar: Condvar,
+    pending: AtomicUsize,
 }
 
-impl ArcWake for Task {
-    fn wake_by_ref(arc_self: &Arc<Self>) {
-        arc_self.executor.send(arc_self.clone())
-            .expect("failed to send task");
+impl Shared {
+    fn schedule(&self, task: Arc<Task>) {
+        self.queue.lock().unwrap().push_back(task);
+        self.condvar.notify_one();
     }
-}
 
-impl Task {
-    fn poll(self: Arc<Self>) {
-        let waker = task::waker(self.clone());
-        let mut cx = Context::from_waker(&waker);
+    fn complete(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _queue = self.queue.lock().unwrap();
+            self.condvar.notify_all();
+        }
+    }
 
-        let mut future_data = self.data.try_lock().unwrap();
-        let polled = future_data.poll_tfd(&mut cx);
-        println!("polled: {:?}", polled);
+    fn work(&self) {
+        loop {
+            let task = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if let Some(task) = queue.pop_front() {
+                        break task;
+                    }
+                    if self.pending.load(Ordering::SeqCst) == 0 {
+                        return;
+                    }
+                    queue = self.condvar.wait(queue).unwrap();
+                }
+            };
+            task.poll();
+        }
     }
 }
 
-struct TaskFutureData {
-    future: Pin<Box<dyn Future<Output = ()> + Send>>,
-    poll: Poll<()>
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3;
+const COMPLETE: u8 = 4;
+
+struct Task {
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    state: AtomicU8,
+    shared: Arc<Shared>,
 }
 
-impl Debug for TaskFutureData {
+impl Debug for Task {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("TaskFutureData")
-            .field("poll", &self.poll)
+        f.debug_struct("Task")
+            .field("state", &self.state.load(Ordering::SeqCst))
             .field("future", &"<future>")
             .finish()
     }
 }
 
-impl TaskFutureData {
-    fn poll_tfd(&mut self, cx: &mut Context<'_>) -> Poll<()> {
-        if self.poll.is_pending() {
-            self.poll = self.future.as_mut().poll(cx);
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let mut state = arc_self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match arc_self.state.compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == IDLE {
+            arc_self.shared.schedule(arc_self.clone());
+        }
+    }
+}
+
+impl Task {
+    fn poll(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let waker = task::waker(self.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = self.future.lock().unwrap();
+        let polled = match future.as_mut() {
+            Some(inner) => std::panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(&mut cx)))
+                .unwrap_or(Poll::Ready(())),
+            None => return,
         };
-        self.poll
+
+        if polled.is_ready() {
+            *future = None;
+            self.state.store(COMPLETE, Ordering::SeqCst);
+            drop(future);
+            self.shared.complete();
+            return;
+        }
+        drop(future);
+
+        if self.state.compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            self.state.store(SCHEDULED, Ordering::SeqCst);
+            self.shared.schedule(self.clone());
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,13 @@
 #![cfg(test)]
 
-use std::sync::Mutex;
-use crate::Runtime;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use crate::time::{interval, sleep, sleep_until, timeout, Elapsed};
+use crate::{JoinError, Runtime};
 
 
 
@@ -37,4 +43,140 @@ async fn compute_something() -> i32 {
 
 async fn compute_something_else() -> i32 {
     15
+}
+
+struct YieldTimes(usize);
+
+impl Future for YieldTimes {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 == 0 {
+            return Poll::Ready(());
+        }
+        self.0 -= 1;
+
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || {
+            waker.wake_by_ref();
+            waker.wake();
+        });
+        Poll::Pending
+    }
+}
+
+#[test]
+fn concurrent_wakeups() {
+    let completed = Arc::new(AtomicUsize::new(0));
+    let mut rt = Runtime::with_workers(4);
+    for _ in 0..32 {
+        let completed = completed.clone();
+        rt.spawn(async move {
+            YieldTimes(20).await;
+            completed.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    rt.run();
+    assert_eq!(completed.load(Ordering::SeqCst), 32);
+}
+
+#[test]
+fn panicking_task_does_not_block_run() {
+    let mut rt = Runtime::with_workers(1);
+    rt.spawn(async {
+        panic!("task panicked");
+    });
+    rt.spawn(async {
+        YieldTimes(3).await;
+    });
+    rt.run();
+}
+
+#[test]
+fn run_without_tasks_returns() {
+    let mut rt = Runtime::new();
+    assert!(rt.workers() >= 1);
+    rt.run();
+}
+
+#[test]
+fn join_handle_returns_output() {
+    let mut rt = Runtime::with_workers(2);
+    let first = rt.spawn(compute_something());
+    let second = rt.spawn(async {
+        YieldTimes(5).await;
+        compute_something_else().await
+    });
+    let sum = rt.spawn(async move {
+        first.await.unwrap() + second.await.unwrap()
+    });
+    rt.run();
+    assert_eq!(sum.join().unwrap(), 25);
+}
+
+#[test]
+fn join_handle_reports_panic() {
+    let mut rt = Runtime::with_workers(1);
+    let handle = rt.spawn(async {
+        panic!("boom");
+    });
+    rt.run();
+    let err = handle.join().unwrap_err();
+    assert!(err.is_panic());
+    assert_eq!(*err.into_panic().downcast::<&str>().unwrap(), "boom");
+}
+
+#[test]
+fn join_handle_abort() {
+    let mut rt = Runtime::with_workers(1);
+    let handle = rt.spawn(std::future::pending::<()>());
+    handle.abort();
+    rt.run();
+    assert!(handle.is_finished());
+    assert!(matches!(handle.join(), Err(JoinError::Cancelled)));
+}
+
+#[test]
+fn sleep_waits_for_duration() {
+    let mut rt = Runtime::with_workers(2);
+    let start = Instant::now();
+    let short = rt.spawn(async move {
+        sleep(Duration::from_millis(20)).await;
+        start.elapsed()
+    });
+    let long = rt.spawn(async move {
+        sleep_until(start + Duration::from_millis(60)).await;
+        start.elapsed()
+    });
+    rt.run();
+    assert!(short.join().unwrap() >= Duration::from_millis(20));
+    assert!(long.join().unwrap() >= Duration::from_millis(60));
+}
+
+#[test]
+fn timeout_elapses_or_completes() {
+    let mut rt = Runtime::with_workers(1);
+    let elapsed = rt.spawn(timeout(Duration::from_millis(10), std::future::pending::<()>()));
+    let completed = rt.spawn(timeout(Duration::from_secs(5), async {
+        sleep(Duration::from_millis(5)).await;
+        compute_something().await
+    }));
+    rt.run();
+    assert_eq!(elapsed.join().unwrap(), Err(Elapsed));
+    assert_eq!(completed.join().unwrap(), Ok(10));
+}
+
+#[test]
+fn interval_ticks_periodically() {
+    let mut rt = Runtime::with_workers(1);
+    let ticks = rt.spawn(async {
+        let start = Instant::now();
+        let mut ticker = interval(Duration::from_millis(10));
+        for _ in 0..4 {
+            ticker.tick().await;
+        }
+        start.elapsed()
+    });
+    rt.run();
+    assert!(ticks.join().unwrap() >= Duration::from_millis(30));
 }
\ No newline at end of file
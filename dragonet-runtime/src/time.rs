@@ -0,0 +1,262 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use futures::Stream;
+
+struct TimerEntry {
+    deadline: Instant,
+    id: u64,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.id).cmp(&(other.deadline, other.id))
+    }
+}
+
+struct TimerHeap {
+    entries: BinaryHeap<Reverse<TimerEntry>>,
+    next_id: u64,
+}
+
+struct Driver {
+    heap: Mutex<TimerHeap>,
+    condvar: Condvar,
+}
+
+impl Driver {
+    fn get() -> &'static Driver {
+        static DRIVER: OnceLock<Driver> = OnceLock::new();
+        DRIVER.get_or_init(|| {
+            std::thread::Builder::new()
+                .name("dragonet-timer".to_string())
+                .spawn(|| Driver::get().run())
+                .expect("failed to spawn timer thread");
+            Driver {
+                heap: Mutex::new(TimerHeap {
+                    entries: BinaryHeap::new(),
+                    next_id: 0,
+                }),
+                condvar: Condvar::new(),
+            }
+        })
+    }
+
+    fn register(&self, deadline: Instant, waker: Arc<Mutex<Option<Waker>>>) {
+        let mut heap = self.heap.lock().unwrap();
+        let id = heap.next_id;
+        heap.next_id += 1;
+        let earliest = heap.entries.peek().is_none_or(|Reverse(entry)| deadline < entry.deadline);
+        heap.entries.push(Reverse(TimerEntry { deadline, id, waker }));
+        if earliest {
+            self.condvar.notify_one();
+        }
+    }
+
+    fn run(&self) {
+        let mut heap = self.heap.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while heap.entries.peek().is_some_and(|Reverse(entry)| entry.deadline <= now) {
+                expired.push(heap.entries.pop().unwrap().0);
+            }
+
+            if !expired.is_empty() {
+                drop(heap);
+                for entry in expired {
+                    if let Some(waker) = entry.waker.lock().unwrap().take() {
+                        waker.wake();
+                    }
+                }
+                heap = self.heap.lock().unwrap();
+                continue;
+            }
+
+            heap = match heap.entries.peek() {
+                Some(Reverse(entry)) => {
+                    let wait = entry.deadline.saturating_duration_since(now);
+                    self.condvar.wait_timeout(heap, wait).unwrap().0
+                }
+                None => self.condvar.wait(heap).unwrap(),
+            };
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    waker: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, waker: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    pub fn reset(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+        if let Some(waker) = self.waker.take() {
+            waker.lock().unwrap().take();
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            return Poll::Ready(());
+        }
+
+        match &self.waker {
+            Some(waker) => {
+                *waker.lock().unwrap() = Some(cx.waker().clone());
+            }
+            None => {
+                let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+                Driver::get().register(self.deadline, waker.clone());
+                self.waker = Some(waker);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.lock().unwrap().take();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+pub struct Timeout<F: Future> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(value) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(value));
+        }
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep_until(Instant::now()),
+    }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let tick = self.sleep.deadline();
+        let now = Instant::now();
+        let mut next = tick + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep.reset(next);
+        Poll::Ready(tick)
+    }
+
+    pub fn tick(&mut self) -> Tick<'_> {
+        Tick { interval: self }
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+pub struct Tick<'a> {
+    interval: &'a mut Interval,
+}
+
+impl Future for Tick<'_> {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Instant> {
+        self.interval.poll_tick(cx)
+    }
+}
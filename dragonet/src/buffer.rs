@@ -1,9 +1,61 @@
+use std::fmt::{Display, Formatter};
 use std::ops::{BitAnd, BitOr, Not, Shl};
+use std::str::Utf8Error;
+
+pub use dragonet_macros::{Decode, Encode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferError {
+    Underflow { needed: usize, remaining: usize },
+    VarIntOverflow,
+    InvalidUtf8(Utf8Error),
+    InvalidVariant(i64),
+    InvalidLength(i64),
+    OutOfRange(i64),
+    StringTooLong { length: usize, max: usize },
+    CollectionTooLong { length: usize, max: usize },
+}
+
+impl Display for BufferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BufferError::Underflow { needed, remaining } =>
+                write!(f, "needed {} bytes but only {} remain", needed, remaining),
+            BufferError::VarIntOverflow => write!(f, "var-int is longer than 10 bytes"),
+            BufferError::InvalidUtf8(err) => write!(f, "string is not valid UTF-8: {}", err),
+            BufferError::InvalidVariant(variant) => write!(f, "unknown enum variant {}", variant),
+            BufferError::InvalidLength(length) => write!(f, "invalid length {}", length),
+            BufferError::OutOfRange(value) => write!(f, "var-int {} is out of range for its field", value),
+            BufferError::StringTooLong { length, max } =>
+                write!(f, "string of {} bytes exceeds the maximum of {} bytes", length, max),
+            BufferError::CollectionTooLong { length, max } =>
+                write!(f, "collection of {} elements exceeds the maximum of {} elements", length, max),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Buffer);
+}
+
+pub trait Decode: Sized {
+    fn decode(buf: &mut Buffer) -> Result<Self, BufferError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VarInt(pub i64);
+
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 1024 * 1024;
+pub const DEFAULT_MAX_COLLECTION_LENGTH: usize = 1024 * 1024;
 
 #[derive(Debug)]
 pub struct Buffer {
     vector: Vec<u8>,
     read_index: usize,
+    max_string_length: usize,
+    max_collection_length: usize,
 }
 
 impl Default for Buffer {
@@ -17,6 +69,8 @@ impl Buffer {
         Buffer {
             vector: vec![],
             read_index: 0,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_collection_length: DEFAULT_MAX_COLLECTION_LENGTH,
         }
     }
 
@@ -24,6 +78,8 @@ impl Buffer {
         Buffer {
             vector: vec![0; capacity],
             read_index: 0,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_collection_length: DEFAULT_MAX_COLLECTION_LENGTH,
         }
     }
 
@@ -51,10 +107,46 @@ impl Buffer {
         self.vector.capacity()
     }
 
+    pub fn max_string_length(&self) -> usize {
+        self.max_string_length
+    }
+
+    pub fn set_max_string_length(&mut self, max_string_length: usize) {
+        self.max_string_length = max_string_length;
+    }
+
+    pub fn max_collection_length(&self) -> usize {
+        self.max_collection_length
+    }
+
+    /// Caps the element count of decoded collections. Elements can decode from zero bytes,
+    /// so the remaining length alone does not bound the work a peer can ask for.
+    pub fn set_max_collection_length(&mut self, max_collection_length: usize) {
+        self.max_collection_length = max_collection_length;
+    }
+
     pub fn reset_reading(&mut self) {
         self.read_index = 0;
     }
 
+    pub fn remaining(&self) -> usize {
+        self.vector.len().saturating_sub(self.read_index)
+    }
+
+    pub fn read_slice(&mut self, length: usize) -> Result<&[u8], BufferError> {
+        if length > self.remaining() {
+            return Err(BufferError::Underflow { needed: length, remaining: self.remaining() });
+        }
+        self.read_index += length;
+        Ok(&self.vector[self.read_index - length..self.read_index])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], BufferError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_slice(N)?);
+        Ok(array)
+    }
+
     pub fn write_all(&mut self, buf: &Buffer) {
         self.vector.extend_from_slice(&buf.vector);
     }
@@ -67,155 +159,94 @@ impl Buffer {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_i8(&mut self) -> i8 {
-        self.read_index += 1;
-        i8::from_be_bytes([
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_i8(&mut self) -> Result<i8, BufferError> {
+        Ok(i8::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_u8(&mut self, value: u8) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        self.read_index += 1;
-        u8::from_be_bytes([
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_u8(&mut self) -> Result<u8, BufferError> {
+        Ok(u8::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_i16(&mut self, value: i16) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_i16(&mut self) -> i16 {
-        self.read_index += 2;
-        i16::from_be_bytes([
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_i16(&mut self) -> Result<i16, BufferError> {
+        Ok(i16::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_u16(&mut self, value: u16) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_u16(&mut self) -> u16 {
-        self.read_index += 2;
-        u16::from_be_bytes([
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_u16(&mut self) -> Result<u16, BufferError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_i32(&mut self, value: i32) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_i32(&mut self) -> i32 {
-        self.read_index += 4;
-        i32::from_be_bytes([
-            self.vector[self.read_index - 4],
-            self.vector[self.read_index - 3],
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_i32(&mut self) -> Result<i32, BufferError> {
+        Ok(i32::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_u32(&mut self, value: u32) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        self.read_index += 4;
-        u32::from_be_bytes([
-            self.vector[self.read_index - 4],
-            self.vector[self.read_index - 3],
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ])
-    }
-
-    pub fn int64_slice(&self) -> [u8; 8] {
-        [
-            self.vector[self.read_index - 8],
-            self.vector[self.read_index - 7],
-            self.vector[self.read_index - 6],
-            self.vector[self.read_index - 5],
-            self.vector[self.read_index - 4],
-            self.vector[self.read_index - 3],
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ]
+    pub fn read_u32(&mut self) -> Result<u32, BufferError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_i64(&mut self, value: i64) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_i64(&mut self) -> i64 {
-        self.read_index += 8;
-        i64::from_be_bytes(self.int64_slice())
+    pub fn read_i64(&mut self) -> Result<i64, BufferError> {
+        Ok(i64::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_u64(&mut self, value: u64) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_u64(&mut self) -> u64 {
-        self.read_index += 8;
-        u64::from_be_bytes(self.int64_slice())
-    }
-
-    pub fn int128_slice(&self) -> [u8; 16] {
-        [
-            self.vector[self.read_index - 16],
-            self.vector[self.read_index - 15],
-            self.vector[self.read_index - 14],
-            self.vector[self.read_index - 13],
-            self.vector[self.read_index - 12],
-            self.vector[self.read_index - 11],
-            self.vector[self.read_index - 10],
-            self.vector[self.read_index - 9],
-            self.vector[self.read_index - 8],
-            self.vector[self.read_index - 7],
-            self.vector[self.read_index - 6],
-            self.vector[self.read_index - 5],
-            self.vector[self.read_index - 4],
-            self.vector[self.read_index - 3],
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ]
+    pub fn read_u64(&mut self) -> Result<u64, BufferError> {
+        Ok(u64::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_i128(&mut self, value: i128) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_i128(&mut self) -> i128 {
-        self.read_index += 16;
-        i128::from_be_bytes(self.int128_slice())
+    pub fn read_i128(&mut self) -> Result<i128, BufferError> {
+        Ok(i128::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_u128(&mut self, value: u128) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_u128(&mut self) -> u128 {
-        self.read_index += 16;
-        u128::from_be_bytes(self.int128_slice())
+    pub fn read_u128(&mut self) -> Result<u128, BufferError> {
+        Ok(u128::from_be_bytes(self.read_array()?))
     }
 
     const SEGMENT_BITS: i64 = 0x7F;
     const CONTINUE_BIT: i64 = 0x80;
 
-    pub fn read_var_int(&mut self) -> i64 {
+    pub fn read_var_int(&mut self) -> Result<i64, BufferError> {
         let mut value: i64 = 0;
         let mut position = 0;
 
         loop {
-            let current_byte = self.read_u8();
+            if position >= 64 {
+                return Err(BufferError::VarIntOverflow);
+            }
+            let current_byte = self.read_u8()?;
 
             value |= (current_byte as i64 & (Buffer::SEGMENT_BITS)) << position;
 
@@ -224,7 +255,7 @@ impl Buffer {
             position += 7;
         }
 
-        value
+        Ok(value)
     }
 
     pub fn write_var_int(&mut self, mut value: i64) {
@@ -246,32 +277,24 @@ impl Buffer {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_f32(&mut self) -> f32 {
-        self.read_index += 4;
-        f32::from_be_bytes([
-            self.vector[self.read_index - 4],
-            self.vector[self.read_index - 3],
-            self.vector[self.read_index - 2],
-            self.vector[self.read_index - 1]
-        ])
+    pub fn read_f32(&mut self) -> Result<f32, BufferError> {
+        Ok(f32::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_f64(&mut self, value: f64) {
         self.vector.extend_from_slice(&value.to_be_bytes());
     }
 
-    pub fn read_f64(&mut self) -> f64 {
-        self.read_index += 8;
-        f64::from_be_bytes(self.int64_slice())
+    pub fn read_f64(&mut self) -> Result<f64, BufferError> {
+        Ok(f64::from_be_bytes(self.read_array()?))
     }
 
     pub fn write_boolean(&mut self, value: bool) {
         self.vector.push(value as u8);
     }
 
-    pub fn read_boolean(&mut self) -> bool {
-        self.read_index += 1;
-        self.vector[self.read_index - 1] == 1
+    pub fn read_boolean(&mut self) -> Result<bool, BufferError> {
+        Ok(self.read_u8()? == 1)
     }
 
     pub fn write_string(&mut self, value: &str) {
@@ -279,26 +302,299 @@ impl Buffer {
         self.vector.extend_from_slice(value.as_bytes());
     }
 
-    pub fn read_string(&mut self) -> String {
-        let length = self.read_var_int();
-        let mut str = String::new();
+    pub fn read_str(&mut self) -> Result<&str, BufferError> {
+        let length = self.read_var_int()?;
+        if length < 0 {
+            return Err(BufferError::InvalidLength(length));
+        }
+
+        let length = length as usize;
+        if length > self.max_string_length {
+            return Err(BufferError::StringTooLong { length, max: self.max_string_length });
+        }
+        std::str::from_utf8(self.read_slice(length)?).map_err(BufferError::InvalidUtf8)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, BufferError> {
+        self.read_str().map(str::to_owned)
+    }
+
+    pub fn write<E: Encode + ?Sized>(&mut self, value: &E) {
+        value.encode(self);
+    }
+
+    pub fn read<D: Decode>(&mut self) -> Result<D, BufferError> {
+        D::decode(self)
+    }
+}
+
+macro_rules! primitive_codec {
+    ($($ty:ty => $write:ident, $read:ident;)*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, buf: &mut Buffer) {
+                    buf.$write(*self);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(buf: &mut Buffer) -> Result<Self, BufferError> {
+                    buf.$read()
+                }
+            }
+        )*
+    };
+}
+
+primitive_codec! {
+    i8 => write_i8, read_i8;
+    u8 => write_u8, read_u8;
+    i16 => write_i16, read_i16;
+    u16 => write_u16, read_u16;
+    i32 => write_i32, read_i32;
+    u32 => write_u32, read_u32;
+    i64 => write_i64, read_i64;
+    u64 => write_u64, read_u64;
+    i128 => write_i128, read_i128;
+    u128 => write_u128, read_u128;
+    f32 => write_f32, read_f32;
+    f64 => write_f64, read_f64;
+    bool => write_boolean, read_boolean;
+}
+
+impl Encode for VarInt {
+    fn encode(&self, buf: &mut Buffer) {
+        buf.write_var_int(self.0);
+    }
+}
+
+impl Decode for VarInt {
+    fn decode(buf: &mut Buffer) -> Result<Self, BufferError> {
+        Ok(VarInt(buf.read_var_int()?))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, buf: &mut Buffer) {
+        buf.write_string(self);
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Buffer) {
+        buf.write_string(self);
+    }
+}
+
+impl Decode for String {
+    fn decode(buf: &mut Buffer) -> Result<Self, BufferError> {
+        buf.read_string()
+    }
+}
+
+impl<E: Encode> Encode for Option<E> {
+    fn encode(&self, buf: &mut Buffer) {
+        buf.write_boolean(self.is_some());
+        if let Some(value) = self {
+            value.encode(buf);
+        }
+    }
+}
+
+impl<D: Decode> Decode for Option<D> {
+    fn decode(buf: &mut Buffer) -> Result<Self, BufferError> {
+        if buf.read_boolean()? {
+            Ok(Some(D::decode(buf)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<E: Encode> Encode for Vec<E> {
+    fn encode(&self, buf: &mut Buffer) {
+        buf.write_var_int(self.len() as i64);
+        for value in self {
+            value.encode(buf);
+        }
+    }
+}
+
+impl<D: Decode> Decode for Vec<D> {
+    fn decode(buf: &mut Buffer) -> Result<Self, BufferError> {
+        let length = buf.read_var_int()?;
+        if length < 0 {
+            return Err(BufferError::InvalidLength(length));
+        }
+
+        let length = length as usize;
+        if length > buf.max_collection_length {
+            return Err(BufferError::CollectionTooLong { length, max: buf.max_collection_length });
+        }
+        let mut values = Vec::with_capacity(length.min(buf.remaining()));
         for _ in 0..length {
-            self.read_index += 1;
-            str.push(self.vector[self.read_index - 1] as char);
-        };
-        str
+            values.push(D::decode(buf)?);
+        }
+        Ok(values)
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::buffer::Buffer;
+    use crate::buffer::{Buffer, BufferError, Decode, Encode, VarInt, DEFAULT_MAX_COLLECTION_LENGTH};
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Login {
+        name: String,
+        #[dragonet(varint)]
+        protocol_version: i32,
+        tags: Vec<String>,
+        session: Option<u64>,
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Small {
+        #[dragonet(varint)]
+        value: u8,
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Unit;
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Wide {
+        #[dragonet(varint)]
+        unsigned: u64,
+        #[dragonet(varint)]
+        signed: i64,
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    enum Action {
+        Jump,
+        Move(f32, f32),
+        Say { message: String, volume: VarInt },
+    }
 
     #[test]
     pub fn test_buffer() {
         let mut buf = Buffer::new();
         buf.write_var_int(328033232455);
         println!("{:?}", buf.vector);
-        println!("{}", buf.read_var_int());
+        println!("{}", buf.read_var_int().unwrap());
+    }
+
+    #[test]
+    pub fn test_short_reads_fail() {
+        let mut buf = Buffer::new();
+        buf.write_u16(7);
+        assert_eq!(buf.read_i32(), Err(BufferError::Underflow { needed: 4, remaining: 2 }));
+        assert_eq!(buf.read_u16(), Ok(7));
+        assert_eq!(buf.read_u8(), Err(BufferError::Underflow { needed: 1, remaining: 0 }));
+    }
+
+    #[test]
+    pub fn test_var_int_overflow() {
+        let mut buf = Buffer::new();
+        buf.write_slice(&[0xFF; 11]);
+        assert_eq!(buf.read_var_int(), Err(BufferError::VarIntOverflow));
+    }
+
+    #[test]
+    pub fn test_invalid_utf8() {
+        let mut buf = Buffer::new();
+        buf.write_var_int(2);
+        buf.write_slice(&[0xC3, 0x28]);
+        assert!(matches!(buf.read_string(), Err(BufferError::InvalidUtf8(_))));
+    }
+
+    #[test]
+    pub fn test_strings() {
+        let mut buf = Buffer::new();
+        buf.write_string("héllo wörld 🐉");
+        buf.write_string("zero copy");
+        assert_eq!(buf.read_string(), Ok("héllo wörld 🐉".to_string()));
+        assert_eq!(buf.read_str(), Ok("zero copy"));
+
+        let mut buf = Buffer::new();
+        buf.write_string("too long");
+        buf.set_max_string_length(4);
+        assert_eq!(buf.read_string(), Err(BufferError::StringTooLong { length: 8, max: 4 }));
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(1 << 40);
+        buf.set_max_string_length(usize::MAX);
+        assert_eq!(buf.read_string(), Err(BufferError::Underflow { needed: 1 << 40, remaining: 0 }));
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(-1);
+        assert_eq!(buf.read_string(), Err(BufferError::InvalidLength(-1)));
+    }
+
+    #[test]
+    pub fn test_derived_codecs() {
+        let login = Login {
+            name: "dragon".to_string(),
+            protocol_version: 300,
+            tags: vec!["a".to_string(), "b".to_string()],
+            session: Some(42),
+        };
+        let actions = vec![
+            Action::Jump,
+            Action::Move(1.5, -2.0),
+            Action::Say { message: "hi".to_string(), volume: VarInt(3) },
+        ];
+
+        let mut buf = Buffer::new();
+        buf.write(&login);
+        buf.write(&actions);
+        assert_eq!(buf.read::<Login>(), Ok(login));
+        assert_eq!(buf.read::<Vec<Action>>(), Ok(actions));
+        assert_eq!(buf.remaining(), 0);
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(7);
+        assert_eq!(buf.read::<Action>(), Err(BufferError::InvalidVariant(7)));
+    }
+
+    #[test]
+    pub fn test_derived_codecs_check_ranges() {
+        let mut buf = Buffer::new();
+        buf.write_var_int(300);
+        assert_eq!(buf.read::<Small>(), Err(BufferError::OutOfRange(300)));
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(-1);
+        assert_eq!(buf.read::<Small>(), Err(BufferError::OutOfRange(-1)));
+
+        for wide in [Wide { unsigned: u64::MAX, signed: i64::MIN }, Wide { unsigned: 1 << 63, signed: i64::MAX }] {
+            let mut buf = Buffer::new();
+            buf.write(&wide);
+            assert_eq!(buf.read::<Wide>(), Ok(wide));
+        }
+
+        let mut buf = Buffer::new();
+        buf.write_string("dragon");
+        buf.write_var_int(1 << 40);
+        assert_eq!(buf.read::<Login>(), Err(BufferError::OutOfRange(1 << 40)));
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(-1);
+        assert_eq!(buf.read::<Vec<u8>>(), Err(BufferError::InvalidLength(-1)));
+
+        let mut buf = Buffer::new();
+        buf.write_var_int(1 << 62);
+        assert_eq!(
+            buf.read::<Vec<Unit>>(),
+            Err(BufferError::CollectionTooLong { length: 1 << 62, max: DEFAULT_MAX_COLLECTION_LENGTH }),
+        );
+
+        let mut buf = Buffer::new();
+        buf.write(&vec![Unit, Unit, Unit]);
+        buf.set_max_collection_length(2);
+        assert_eq!(buf.read::<Vec<Unit>>(), Err(BufferError::CollectionTooLong { length: 3, max: 2 }));
+        buf.reset_reading();
+        buf.set_max_collection_length(3);
+        assert_eq!(buf.read::<Vec<Unit>>(), Ok(vec![Unit, Unit, Unit]));
     }
 }
\ No newline at end of file
@@ -2,30 +2,80 @@ mod refs;
 
 use std::any::Any;
 use std::collections::HashMap;
+use std::io;
 use std::io::ErrorKind::{Interrupted, WouldBlock};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use mio::{Events, Interest, Poll, Token, Waker};
 use crate::buffer::Buffer;
-use crate::client::refs::ClientRef;
-use crate::protocol::{PacketDirection, PacketMetadata, PacketState, Protocol};
+pub use crate::client::refs::ClientRef;
+use crate::protocol::{check_packet, decode_packet, DecodeError, PacketDirection, PacketKind, PacketMetadata, PacketState, Protocol, ProtocolViolation, ViolationPolicy};
+use crate::buffer::{DEFAULT_MAX_COLLECTION_LENGTH, DEFAULT_MAX_STRING_LENGTH};
+use crate::disconnect::DisconnectReason;
+use crate::framing::{retain_frames, write_control_frame, FrameDecoder, FrameKind, ReadState, DEFAULT_MAX_FRAME_SIZE};
+use crate::keepalive::{Heartbeat, HeartbeatAction, KeepAlive};
+use crate::reconnect::{random_unit, Backoff, QueuedPackets, ReconnectPolicy};
+use crate::request::{RequestError, RequestId, ResponseSlot};
+use crate::transport::{Address, Stream};
 
-type ClientPacketEvent<S, T> = fn(ClientRef<S, T>, &T);
+const STREAM: Token = Token(0);
+const WAKER: Token = Token(1);
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ClientResolver = Arc<dyn Fn() -> io::Result<Vec<Address>> + Send + Sync>;
+type ClientConnectEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>) + Send + Sync>;
+type ClientConnectErrorEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, &io::Error) + Send + Sync>;
+type ClientPacketEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, &T) + Send + Sync>;
+type ClientRoute<S, T> = Arc<dyn Fn(ClientRef<S, T>, &T) -> bool + Send + Sync>;
+type ClientViolationEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, &ProtocolViolation) + Send + Sync>;
+type ClientDisconnectEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, &DisconnectReason) + Send + Sync>;
+type ClientReconnectingEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, u32, Duration) + Send + Sync>;
+type ClientReconnectedEvent<S, T> = Arc<dyn Fn(ClientRef<S, T>, u32) + Send + Sync>;
+
+pub(crate) struct ClientSession {
+    pub(crate) stream: Stream,
+    decoder: FrameDecoder,
+    write_buffer: Vec<u8>,
+}
 
 pub struct Client<S, T>
 where
     S: PacketState,
     T: Protocol<S>,
 {
-    socket: Option<TcpStream>,
+    resolver: ClientResolver,
+    connect_timeout: Duration,
     events: Vec<ClientPacketEvent<S, T>>,
-    on_connection: fn(ClientRef<S, T>),
-    packet_queue: Vec<T>,
+    routes: Vec<ClientRoute<S, T>>,
+    fallback_event: Option<ClientPacketEvent<S, T>>,
+    on_connection: ClientConnectEvent<S, T>,
+    on_connection_error: ClientConnectErrorEvent<S, T>,
+    on_disconnection: ClientDisconnectEvent<S, T>,
+    on_reconnection_attempt: ClientReconnectingEvent<S, T>,
+    on_reconnection: ClientReconnectedEvent<S, T>,
+    reconnect: Option<ReconnectPolicy>,
+    reconnect_state: Option<S>,
+    on_violation: ClientViolationEvent<S, T>,
+    violation_policy: ViolationPolicy,
+    pub(crate) packet_queue: Vec<u8>,
+    pub(crate) pending: HashMap<RequestId, Arc<ResponseSlot<T>>>,
+    next_request_id: u32,
     state: Option<S>,
+    waker: Option<Arc<Waker>>,
+    closing: bool,
+    max_frame_size: usize,
+    max_string_length: usize,
+    max_collection_length: usize,
+    keepalive: Option<KeepAlive>,
+    pub(crate) heartbeat: Heartbeat,
     _phantom: PhantomData<(S, T)>,
 }
 
@@ -41,32 +91,597 @@ impl<S: PacketState, T: Protocol<S>> Default for Client<S, T> {
 impl<S: PacketState, T: Protocol<S>> Client<S, T> {
     pub fn new() -> Client<S, T> {
         Client {
-            socket: None,
+            resolver: Arc::new(|| Ok(Vec::new())),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
             events: Vec::new(),
-            on_connection: |_| {},
+            routes: Vec::new(),
+            fallback_event: None,
+            on_connection: Arc::new(|_| {}),
+            on_connection_error: Arc::new(|_, _| {}),
+            on_disconnection: Arc::new(|_, _| {}),
+            on_reconnection_attempt: Arc::new(|_, _, _| {}),
+            on_reconnection: Arc::new(|_, _| {}),
+            reconnect: None,
+            reconnect_state: None,
+            on_violation: Arc::new(|_, _| {}),
+            violation_policy: ViolationPolicy::default(),
             packet_queue: vec![],
+            pending: HashMap::new(),
+            next_request_id: 0,
             state: None,
+            waker: None,
+            closing: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_collection_length: DEFAULT_MAX_COLLECTION_LENGTH,
+            keepalive: None,
+            heartbeat: Heartbeat::new(None, Instant::now()),
             _phantom: PhantomData,
         }
     }
 
-    pub fn with_address(&mut self, addr: SocketAddrV4) -> &mut Client<S, T> {
-        self.socket = Some(TcpStream::connect(SocketAddr::V4(addr)).unwrap());
+    /// The address is resolved by `event_loop` before every connection attempt, so lookup
+    /// failures are reported through `on_connect_error` and reconnects see DNS changes.
+    /// Each resolved address is tried in order.
+    pub fn with_address<A>(&mut self, addr: A) -> &mut Client<S, T>
+    where
+        A: ToSocketAddrs + Send + Sync + 'static,
+    {
+        self.resolver = Arc::new(move || Ok(addr.to_socket_addrs()?.map(Address::Tcp).collect()));
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_unix_path(&mut self, path: impl AsRef<Path>) -> &mut Client<S, T> {
+        let address = Address::Unix(Some(path.as_ref().to_path_buf()));
+        self.resolver = Arc::new(move || Ok(vec![address.clone()]));
+        self
+    }
+
+    pub fn with_connect_timeout(&mut self, timeout: Duration) -> &mut Client<S, T> {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn with_max_frame_size(&mut self, max_frame_size: usize) -> &mut Client<S, T> {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn with_max_string_length(&mut self, max_string_length: usize) -> &mut Client<S, T> {
+        self.max_string_length = max_string_length;
+        self
+    }
+
+    pub fn with_max_collection_length(&mut self, max_collection_length: usize) -> &mut Client<S, T> {
+        self.max_collection_length = max_collection_length;
+        self
+    }
+
+    pub fn with_packet_event<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, &T) + Send + Sync + 'static,
+    {
+        self.events.push(Arc::new(function));
+        self
+    }
+
+    pub fn on<P>(
+        &mut self,
+        function: impl for<'a> Fn(ClientRef<S, T>, P::Payload<'a>) + Send + Sync + 'static,
+    ) -> &mut Client<S, T>
+    where
+        P: PacketKind<S, T> + 'static,
+    {
+        self.routes.push(Arc::new(move |client, packet| match P::payload(packet) {
+            Some(payload) => {
+                function(client, payload);
+                true
+            }
+            None => false,
+        }));
+        self
+    }
+
+    pub fn with_fallback_event<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, &T) + Send + Sync + 'static,
+    {
+        self.fallback_event = Some(Arc::new(function));
+        self
+    }
+
+    pub fn on_connect<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>) + Send + Sync + 'static,
+    {
+        self.on_connection = Arc::new(function);
         self
     }
 
-    pub fn with_packet_event(&mut self, function: ClientPacketEvent<S, T>) -> &mut Client<S, T> {
-        self.events.push(function);
+    pub fn on_connect_error<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, &io::Error) + Send + Sync + 'static,
+    {
+        self.on_connection_error = Arc::new(function);
         self
     }
 
-    pub fn on_connect(&mut self, function: fn(ClientRef<S, T>)) -> &mut Client<S, T> {
-        self.on_connection = function;
+    pub fn on_disconnect<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, &DisconnectReason) + Send + Sync + 'static,
+    {
+        self.on_disconnection = Arc::new(function);
         self
     }
 
-    pub fn event_loop(mut self) {
-        
+    pub fn with_reconnect(&mut self, policy: ReconnectPolicy) -> &mut Client<S, T> {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// State the client falls back to as soon as the connection drops, so packets
+    /// queued while reconnecting are checked against it.
+    pub fn with_reconnect_state(&mut self, state: S) -> &mut Client<S, T> {
+        self.reconnect_state = Some(state);
+        self
+    }
+
+    pub fn on_reconnecting<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, u32, Duration) + Send + Sync + 'static,
+    {
+        self.on_reconnection_attempt = Arc::new(function);
+        self
+    }
+
+    pub fn on_reconnected<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, u32) + Send + Sync + 'static,
+    {
+        self.on_reconnection = Arc::new(function);
+        self
+    }
+
+    pub fn with_keepalive(&mut self, interval: Duration, timeout: Duration) -> &mut Client<S, T> {
+        self.keepalive = Some(KeepAlive::new(interval, timeout));
+        self
+    }
+
+    pub fn with_violation_policy(&mut self, policy: ViolationPolicy) -> &mut Client<S, T> {
+        self.violation_policy = policy;
+        self
+    }
+
+    pub fn on_protocol_violation<F>(&mut self, function: F) -> &mut Client<S, T>
+    where
+        F: Fn(ClientRef<S, T>, &ProtocolViolation) + Send + Sync + 'static,
+    {
+        self.on_violation = Arc::new(function);
+        self
+    }
+
+    pub fn event_loop(self) -> io::Result<()> {
+        let client_ref = ClientRef::new(self);
+        let mut stream = Client::try_connect(&client_ref)?;
+        let mut reconnected = None;
+        loop {
+            let reason = Client::session(&client_ref, stream, reconnected)?;
+            let policy = {
+                let mut client = client_ref.lock();
+                match (client.reconnect, &reason) {
+                    (Some(policy), reason) if !client.closing && !matches!(reason, DisconnectReason::Closed) => {
+                        if let Some(state) = client.reconnect_state.clone() {
+                            client.state = Some(state);
+                        }
+                        match policy.queued_packets {
+                            QueuedPackets::Replay => retain_frames(&mut client.packet_queue, |kind| kind == FrameKind::Packet),
+                            QueuedPackets::Discard => client.packet_queue.clear(),
+                        }
+                        Some(policy)
+                    }
+                    _ => None,
+                }
+            };
+            let Some(policy) = policy else {
+                return match reason {
+                    DisconnectReason::Io(err) => Err(err),
+                    DisconnectReason::Decode(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                    DisconnectReason::ProtocolViolation(violation) => Err(io::Error::new(io::ErrorKind::InvalidData, violation)),
+                    _ => Ok(()),
+                };
+            };
+
+            let mut backoff = Backoff::new(policy);
+            let mut last_error = None;
+            stream = loop {
+                let Some(delay) = backoff.next_delay(random_unit()) else {
+                    return Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "reconnect attempts exhausted")));
+                };
+                let on_reconnection_attempt = client_ref.lock().on_reconnection_attempt.clone();
+                on_reconnection_attempt(client_ref.clone(), backoff.attempt(), delay);
+                if client_ref.wait_for_disconnect(delay) {
+                    return Ok(());
+                }
+                match Client::try_connect(&client_ref) {
+                    Ok(stream) => break stream,
+                    Err(err) => last_error = Some(err),
+                }
+            };
+            reconnected = Some(backoff.attempt());
+        }
+    }
+
+    fn try_connect(client: &ClientRef<S, T>) -> io::Result<Stream> {
+        let (resolver, timeout) = {
+            let client = client.lock();
+            (client.resolver.clone(), client.connect_timeout)
+        };
+        resolver().and_then(|addresses| Client::<S, T>::connect(&addresses, timeout)).inspect_err(|err| {
+            let on_connection_error = client.lock().on_connection_error.clone();
+            on_connection_error(client.clone(), err);
+        })
+    }
+
+    fn session(
+        client_ref: &ClientRef<S, T>,
+        mut stream: Stream,
+        reconnected: Option<u32>,
+    ) -> io::Result<DisconnectReason> {
+        let mut poll = Poll::new()?;
+        poll.registry().register(&mut stream, STREAM, Interest::READABLE | Interest::WRITABLE)?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+
+        let mut session = Client::begin_session(client_ref, stream, waker, reconnected);
+        let reason = Client::drive(client_ref, &mut poll, &mut session);
+        let _ = poll.registry().deregister(&mut session.stream);
+        Client::end_session(client_ref, session, &reason);
+        Ok(reason)
+    }
+
+    pub(crate) fn begin_session(
+        client_ref: &ClientRef<S, T>,
+        stream: Stream,
+        waker: Arc<Waker>,
+        reconnected: Option<u32>,
+    ) -> ClientSession {
+        let decoder = {
+            let mut client = client_ref.lock();
+            client.waker = Some(waker);
+            client.heartbeat = Heartbeat::new(client.keepalive, Instant::now());
+            let mut decoder = FrameDecoder::new(client.max_frame_size);
+            decoder.set_max_string_length(client.max_string_length);
+            decoder.set_max_collection_length(client.max_collection_length);
+            decoder
+        };
+        let on_connection = client_ref.lock().on_connection.clone();
+        on_connection(client_ref.clone());
+        if let Some(attempts) = reconnected {
+            let on_reconnection = client_ref.lock().on_reconnection.clone();
+            on_reconnection(client_ref.clone(), attempts);
+        }
+        ClientSession { stream, decoder, write_buffer: Vec::new() }
+    }
+
+    pub(crate) fn end_session(client_ref: &ClientRef<S, T>, session: ClientSession, reason: &DisconnectReason) {
+        let _ = session.stream.shutdown(Shutdown::Both);
+        let pending = {
+            let mut client = client_ref.lock();
+            client.waker = None;
+            std::mem::take(&mut client.pending)
+        };
+        for slot in pending.into_values() {
+            slot.complete(Err(RequestError::Disconnected));
+        }
+
+        let on_disconnection = client_ref.lock().on_disconnection.clone();
+        on_disconnection(client_ref.clone(), reason);
+    }
+
+    fn connect(addresses: &[Address], timeout: Duration) -> io::Result<Stream> {
+        let mut last_error = None;
+        for addr in addresses {
+            match Stream::connect(addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "client has no address")))
+    }
+
+    fn drive(client: &ClientRef<S, T>, poll: &mut Poll, session: &mut ClientSession) -> DisconnectReason {
+        let poll_timeout = KeepAlive::poll_timeout(client.lock().keepalive);
+        let mut events = Events::with_capacity(16);
+        loop {
+            if let Err(err) = poll.poll(&mut events, poll_timeout) {
+                if err.kind() == Interrupted {
+                    continue;
+                }
+                return DisconnectReason::Io(err);
+            }
+
+            let readable = events.iter().any(|event| event.token() == STREAM && event.is_readable());
+            if let Some(reason) = Client::step(client, session, readable) {
+                return reason;
+            }
+        }
+    }
+
+    pub(crate) fn step(client: &ClientRef<S, T>, session: &mut ClientSession, readable: bool) -> Option<DisconnectReason> {
+        let mut open = true;
+        if readable {
+            loop {
+                let read = match session.decoder.read_from(&mut session.stream) {
+                    Ok(read) => read,
+                    Err(err) => return Some(DisconnectReason::Io(err)),
+                };
+                open = read != ReadState::Closed;
+                loop {
+                    if client.lock().closing {
+                        break;
+                    }
+                    match session.decoder.next_frame() {
+                        Ok(Some(frame)) => {
+                            if let Err(err) = Client::dispatch(client, frame) {
+                                return Some(err.into());
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(err) => return Some(DisconnectReason::Decode(err.into())),
+                    }
+                }
+                if read != ReadState::Full || client.lock().closing {
+                    break;
+                }
+            }
+        }
+
+        let action = client.lock().heartbeat.poll(Instant::now());
+        match action {
+            HeartbeatAction::Idle => {}
+            HeartbeatAction::Ping(nonce) => write_control_frame(&mut client.lock().packet_queue, FrameKind::Ping(nonce)),
+            HeartbeatAction::TimedOut => return Some(DisconnectReason::Timeout),
+        }
+
+        if let Err(err) = Client::flush(client, &mut session.stream, &mut session.write_buffer) {
+            return Some(DisconnectReason::Io(err));
+        }
+        let drained = {
+            let client = client.lock();
+            client.closing && client.packet_queue.is_empty()
+        };
+        if drained && session.write_buffer.is_empty() {
+            return Some(DisconnectReason::Closed);
+        }
+        if !open {
+            return Some(DisconnectReason::RemoteClosed);
+        }
+        None
+    }
+
+    fn handle_control(&mut self, kind: FrameKind, now: Instant) -> bool {
+        self.heartbeat.received(now);
+        match kind {
+            FrameKind::Ping(nonce) => write_control_frame(&mut self.packet_queue, FrameKind::Pong(nonce)),
+            FrameKind::Pong(nonce) => self.heartbeat.pong(nonce, now),
+            _ => return false,
+        }
+        true
+    }
+
+    pub(crate) fn next_request_id(&mut self) -> RequestId {
+        let id = RequestId::new(self.next_request_id);
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        id
+    }
+
+    pub(crate) fn current_state(&self) -> S {
+        self.state.clone().unwrap_or_else(|| S::get_state_by_id(0))
+    }
+
+    fn dispatch(client: &ClientRef<S, T>, mut frame: Buffer) -> Result<(), DecodeError> {
+        let kind = FrameKind::read(&mut frame)?;
+        if client.lock().handle_control(kind, Instant::now()) {
+            return Ok(());
+        }
+        let state = client.lock().current_state();
+        let packet = match decode_packet::<S, T>(&mut frame, state, PacketDirection::Clientbound) {
+            Ok(packet) => packet,
+            Err(DecodeError::Violation(violation)) => {
+                let (policy, on_violation) = {
+                    let client = client.lock();
+                    (client.violation_policy, client.on_violation.clone())
+                };
+                on_violation(client.clone(), &violation);
+                return match policy {
+                    ViolationPolicy::Drop => Ok(()),
+                    ViolationPolicy::Disconnect => Err(DecodeError::Violation(violation)),
+                };
+            }
+            Err(err) => return Err(err),
+        };
+
+        match kind {
+            FrameKind::Packet => {}
+            FrameKind::Request(_) | FrameKind::Ping(_) | FrameKind::Pong(_) => return Ok(()),
+            FrameKind::Response(id) => {
+                let slot = client.lock().pending.remove(&id);
+                if let Some(slot) = slot {
+                    slot.respond(packet);
+                }
+                return Ok(());
+            }
+        }
+
+        let (events, routes, fallback_event) = {
+            let client = client.lock();
+            (client.events.clone(), client.routes.clone(), client.fallback_event.clone())
+        };
+        for event in events {
+            event(client.clone(), &packet);
+        }
+
+        let mut routed = false;
+        for route in routes {
+            routed |= route(client.clone(), &packet);
+        }
+        if let (false, Some(event)) = (routed, fallback_event) {
+            event(client.clone(), &packet);
+        }
+        Ok(())
+    }
+
+    fn flush(client: &ClientRef<S, T>, stream: &mut Stream, write_buffer: &mut Vec<u8>) -> io::Result<()> {
+        write_buffer.append(&mut client.lock().packet_queue);
+
+        while !write_buffer.is_empty() {
+            match stream.write(write_buffer) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    write_buffer.drain(..written);
+                }
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) if err.kind() == Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
     }
 }
 
+#[cfg(test)]
+pub mod tests {
+    use std::io;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use crate::client::Client;
+    use crate::protocol::tests::{Packets, ProtocolState};
+    use crate::reconnect::ReconnectPolicy;
+
+    #[test]
+    pub fn test_connect_is_deferred_to_event_loop() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        let flag = failed.clone();
+        client
+            .with_address(addr)
+            .with_connect_timeout(Duration::from_secs(1))
+            .on_connect_error(move |_, err| {
+                assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+                flag.store(true, Ordering::SeqCst);
+            });
+        assert!(!failed.load(Ordering::SeqCst));
+
+        let err = client.event_loop().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(failed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_address_is_resolved_by_event_loop() {
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        let flag = failed.clone();
+        client
+            .with_address("not a socket address")
+            .on_connect_error(move |_, err| {
+                assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+                flag.store(true, Ordering::SeqCst);
+            });
+        assert!(!failed.load(Ordering::SeqCst));
+
+        assert_eq!(client.event_loop().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(failed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_reconnect_resets_state_and_replays_queue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let connects = Arc::new(AtomicU32::new(0));
+        let reconnects = Arc::new(AtomicU32::new(0));
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        let (connect_count, reconnect_count) = (connects.clone(), reconnects.clone());
+        client
+            .with_address(addr)
+            .with_reconnect(ReconnectPolicy::new(Duration::from_millis(10), Duration::from_millis(50)))
+            .with_reconnect_state(ProtocolState::Login)
+            .on_connect(move |crf| {
+                if connect_count.fetch_add(1, Ordering::SeqCst) == 0 {
+                    crf.set_state(ProtocolState::Play);
+                }
+            })
+            .on_reconnecting(|crf, attempt, _| {
+                assert_eq!(attempt, 1);
+                assert_eq!(crf.state(), ProtocolState::Login);
+                crf.send_packet(Packets::C2SHello("again".to_string())).unwrap();
+            })
+            .on_reconnected(move |crf, attempts| {
+                assert_eq!(attempts, 1);
+                reconnect_count.fetch_add(1, Ordering::SeqCst);
+                crf.disconnect();
+            });
+
+        client.event_loop().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(reconnects.load(Ordering::SeqCst), 1);
+        let received = server.join().unwrap();
+        assert!(received.windows(5).any(|window| window == b"again"));
+    }
+
+    #[test]
+    pub fn test_disconnect_flushes_queued_packets() {
+        const SIZE: usize = 8 * 1024 * 1024;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received.len()
+        });
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        client
+            .with_address(addr)
+            .on_connect(|crf| {
+                crf.send_packet(Packets::C2SHello("x".repeat(SIZE))).unwrap();
+                crf.disconnect();
+            });
+        client.event_loop().unwrap();
+        assert!(server.join().unwrap() > SIZE);
+    }
+
+    #[test]
+    pub fn test_disconnect_interrupts_reconnect_delay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || drop(listener.accept().unwrap()));
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        client
+            .with_address(addr)
+            .with_reconnect(ReconnectPolicy::new(Duration::from_secs(60), Duration::from_secs(60)))
+            .on_reconnecting(|crf, _, _| crf.disconnect());
+
+        let started = Instant::now();
+        client.event_loop().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(30));
+        server.join().unwrap();
+    }
+}
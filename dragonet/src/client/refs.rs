@@ -1,6 +1,9 @@
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
 use crate::client::Client;
-use crate::protocol::{PacketState, Protocol};
+use crate::framing::{write_packet_frame, FrameKind};
+use crate::protocol::{check_packet, PacketDirection, PacketKind, PacketState, Protocol, ProtocolViolation, RequestKind};
+use crate::request::{PendingResponse, RequestError, ResponseSlot};
 
 pub struct ClientRef<S, T>
 where
@@ -8,6 +11,7 @@ where
     T: Protocol<S>,
 {
     pub(crate) client: Arc<Mutex<Client<S, T>>>,
+    closed: Arc<Condvar>,
 }
 
 impl<S, T> Clone for ClientRef<S, T>
@@ -17,7 +21,8 @@ where
 {
     fn clone(&self) -> Self {
         ClientRef {
-            client: self.client.clone()
+            client: self.client.clone(),
+            closed: self.closed.clone(),
         }
     }
 }
@@ -31,10 +36,76 @@ where
         self.client.lock().unwrap().state = Some(state);
     }
 
-    pub fn send_packet(&self, packet: T) {
-        println!("sending packet {:?}", packet);
-        self.client.lock().unwrap().packet_queue.push(packet);
-        println!("{:?}", self.client.lock().unwrap().packet_queue);
+    pub fn rtt(&self) -> Option<Duration> {
+        self.lock().heartbeat.rtt()
+    }
+
+    pub fn state(&self) -> S {
+        self.lock().current_state()
+    }
+
+    pub fn send_packet(&self, packet: T) -> Result<(), ProtocolViolation> {
+        let mut client = self.lock();
+        check_packet(&packet, &client.current_state(), PacketDirection::Serverbound)?;
+        write_packet_frame(&mut client.packet_queue, FrameKind::Packet, &packet.encode());
+        if let Some(waker) = &client.waker {
+            let _ = waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn send_request<P>(&self, request: P::Owned) -> Result<PendingResponse<S, T, P::Response>, RequestError>
+    where
+        P: RequestKind<S, T>,
+    {
+        let packet = P::from_owned(request);
+        let mut client = self.lock();
+        check_packet(&packet, &client.current_state(), PacketDirection::Serverbound)?;
+        let Some(waker) = client.waker.clone() else {
+            return Err(RequestError::Disconnected);
+        };
+
+        let id = client.next_request_id();
+        let slot = ResponseSlot::new(|response| P::Response::payload(response).is_some());
+        client.pending.insert(id, slot.clone());
+        write_packet_frame(&mut client.packet_queue, FrameKind::Request(id), &packet.encode());
+        let _ = waker.wake();
+        Ok(PendingResponse::new(id, slot, Arc::downgrade(&self.client)))
+    }
+
+    pub fn request<P>(
+        &self,
+        request: P::Owned,
+        timeout: Duration,
+    ) -> Result<<P::Response as PacketKind<S, T>>::Owned, RequestError>
+    where
+        P: RequestKind<S, T>,
+    {
+        self.send_request::<P>(request)?.wait(timeout)
+    }
+
+    pub fn disconnect(&self) {
+        let mut client = self.lock();
+        client.closing = true;
+        if let Some(waker) = &client.waker {
+            let _ = waker.wake();
+        }
+        self.closed.notify_all();
+    }
+
+    pub(crate) fn new(client: Client<S, T>) -> ClientRef<S, T> {
+        ClientRef { client: Arc::new(Mutex::new(client)), closed: Arc::new(Condvar::new()) }
+    }
+
+    /// Blocks for `timeout` or until `disconnect` is called, returning true in the latter case.
+    pub(crate) fn wait_for_disconnect(&self, timeout: Duration) -> bool {
+        let client = self.lock();
+        let (client, _) = self.closed.wait_timeout_while(client, timeout, |client| !client.closing).unwrap();
+        client.closing
+    }
+
+    pub(crate) fn same_client(&self, other: &ClientRef<S, T>) -> bool {
+        Arc::ptr_eq(&self.client, &other.client)
     }
 
     pub(crate) fn lock(&self) -> MutexGuard<'_, Client<S, T>> {
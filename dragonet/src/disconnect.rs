@@ -0,0 +1,51 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use crate::protocol::{DecodeError, ProtocolViolation};
+
+#[derive(Debug)]
+pub enum DisconnectReason {
+    RemoteClosed,
+    Io(io::Error),
+    Decode(DecodeError),
+    ProtocolViolation(ProtocolViolation),
+    Kicked(String),
+    Closed,
+    Timeout,
+    Shutdown,
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::RemoteClosed => write!(f, "connection closed by peer"),
+            DisconnectReason::Io(err) => write!(f, "io error: {}", err),
+            DisconnectReason::Decode(err) => write!(f, "decode error: {}", err),
+            DisconnectReason::ProtocolViolation(violation) => write!(f, "protocol violation: {}", violation),
+            DisconnectReason::Kicked(reason) => write!(f, "kicked: {}", reason),
+            DisconnectReason::Closed => write!(f, "connection closed locally"),
+            DisconnectReason::Timeout => write!(f, "connection timed out"),
+            DisconnectReason::Shutdown => write!(f, "server shutting down"),
+        }
+    }
+}
+
+impl From<io::Error> for DisconnectReason {
+    fn from(value: io::Error) -> Self {
+        DisconnectReason::Io(value)
+    }
+}
+
+impl From<DecodeError> for DisconnectReason {
+    fn from(value: DecodeError) -> Self {
+        match value {
+            DecodeError::Violation(violation) => DisconnectReason::ProtocolViolation(violation),
+            value => DisconnectReason::Decode(value),
+        }
+    }
+}
+
+impl From<ProtocolViolation> for DisconnectReason {
+    fn from(value: ProtocolViolation) -> Self {
+        DisconnectReason::ProtocolViolation(value)
+    }
+}
\ No newline at end of file
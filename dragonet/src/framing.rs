@@ -0,0 +1,348 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::ErrorKind::{Interrupted, WouldBlock};
+use std::io::Read;
+use crate::buffer::{Buffer, BufferError, DEFAULT_MAX_COLLECTION_LENGTH, DEFAULT_MAX_STRING_LENGTH};
+use crate::request::RequestId;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 2 * 1024 * 1024;
+
+const MAX_LENGTH_BYTES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    MalformedLength,
+    TooLarge { length: usize, max: usize },
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::MalformedLength => write!(f, "frame length prefix is malformed"),
+            FrameError::TooLarge { length, max } =>
+                write!(f, "frame of {} bytes exceeds the maximum of {} bytes", length, max),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<FrameError> for io::Error {
+    fn from(value: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Packet,
+    Request(RequestId),
+    Response(RequestId),
+    Ping(u32),
+    Pong(u32),
+}
+
+impl FrameKind {
+    pub fn write(&self, buf: &mut Buffer) {
+        match self {
+            FrameKind::Packet => buf.write_u8(0),
+            FrameKind::Request(id) => {
+                buf.write_u8(1);
+                buf.write_var_int(id.get() as i64);
+            }
+            FrameKind::Response(id) => {
+                buf.write_u8(2);
+                buf.write_var_int(id.get() as i64);
+            }
+            FrameKind::Ping(nonce) => {
+                buf.write_u8(3);
+                buf.write_var_int(*nonce as i64);
+            }
+            FrameKind::Pong(nonce) => {
+                buf.write_u8(4);
+                buf.write_var_int(*nonce as i64);
+            }
+        }
+    }
+
+    pub fn read(buf: &mut Buffer) -> Result<FrameKind, BufferError> {
+        match buf.read_u8()? {
+            0 => Ok(FrameKind::Packet),
+            1 => Ok(FrameKind::Request(RequestId::new(read_id(buf)?))),
+            2 => Ok(FrameKind::Response(RequestId::new(read_id(buf)?))),
+            3 => Ok(FrameKind::Ping(read_id(buf)?)),
+            4 => Ok(FrameKind::Pong(read_id(buf)?)),
+            kind => Err(BufferError::InvalidVariant(kind as i64)),
+        }
+    }
+}
+
+fn read_id(buf: &mut Buffer) -> Result<u32, BufferError> {
+    let id = buf.read_var_int()?;
+    u32::try_from(id).map_err(|_| BufferError::InvalidVariant(id))
+}
+
+pub fn write_packet_frame(out: &mut Vec<u8>, kind: FrameKind, packet: &Buffer) {
+    let mut header = Buffer::new();
+    kind.write(&mut header);
+    let mut prefix = Buffer::new();
+    prefix.write_var_int((header.length() + packet.length()) as i64);
+    out.extend_from_slice(prefix.as_array());
+    out.extend_from_slice(header.as_array());
+    out.extend_from_slice(packet.as_array());
+}
+
+pub fn write_control_frame(out: &mut Vec<u8>, kind: FrameKind) {
+    write_packet_frame(out, kind, &Buffer::new());
+}
+
+/// Rewrites the frames already encoded in `out`, keeping those whose kind passes `keep`.
+pub(crate) fn retain_frames(out: &mut Vec<u8>, keep: impl Fn(FrameKind) -> bool) {
+    let mut decoder = FrameDecoder::new(out.len());
+    decoder.push(out);
+    out.clear();
+    while let Ok(Some(mut frame)) = decoder.next_frame() {
+        let Ok(kind) = FrameKind::read(&mut frame) else { continue };
+        if keep(kind) {
+            let mut packet = Buffer::new();
+            if let Ok(rest) = frame.read_slice(frame.remaining()) {
+                packet.write_slice(rest);
+            }
+            write_packet_frame(out, kind, &packet);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadState {
+    Open,
+    Full,
+    Closed,
+}
+
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    cursor: usize,
+    max_frame_size: usize,
+    max_string_length: usize,
+    max_collection_length: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> FrameDecoder {
+        FrameDecoder {
+            buffer: Vec::new(),
+            cursor: 0,
+            max_frame_size,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_collection_length: DEFAULT_MAX_COLLECTION_LENGTH,
+        }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    pub fn set_max_string_length(&mut self, max_string_length: usize) {
+        self.max_string_length = max_string_length;
+    }
+
+    pub fn set_max_collection_length(&mut self, max_collection_length: usize) {
+        self.max_collection_length = max_collection_length;
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.cursor
+    }
+
+    /// True once a complete frame of the maximum size could be buffered, at which point
+    /// callers should decode before reading more.
+    pub fn is_full(&self) -> bool {
+        self.buffered() >= self.max_frame_size.saturating_add(MAX_LENGTH_BYTES)
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.cursor > 0 {
+            self.buffer.drain(..self.cursor);
+            self.cursor = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Reads from a non-blocking `reader` until it would block, hits EOF or the decoder
+    /// fills up. After `ReadState::Full`, decode the buffered frames and read again.
+    pub fn read_from(&mut self, reader: &mut impl Read) -> io::Result<ReadState> {
+        let mut chunk = [0; 4096];
+        while !self.is_full() {
+            match reader.read(&mut chunk) {
+                Ok(0) => return Ok(ReadState::Closed),
+                Ok(read) => self.push(&chunk[..read]),
+                Err(err) if err.kind() == WouldBlock => return Ok(ReadState::Open),
+                Err(err) if err.kind() == Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(ReadState::Full)
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<Buffer>, FrameError> {
+        let buffered = &self.buffer[self.cursor..];
+        let mut length = 0usize;
+        for (index, byte) in buffered.iter().enumerate() {
+            if index >= MAX_LENGTH_BYTES {
+                return Err(FrameError::MalformedLength);
+            }
+            length |= ((byte & 0x7F) as usize) << (index * 7);
+            if byte & 0x80 != 0 {
+                continue;
+            }
+
+            if length > self.max_frame_size {
+                return Err(FrameError::TooLarge { length, max: self.max_frame_size });
+            }
+
+            let start = index + 1;
+            if buffered.len() - start < length {
+                return Ok(None);
+            }
+
+            let mut frame = Buffer::new();
+            frame.set_max_string_length(self.max_string_length);
+            frame.set_max_collection_length(self.max_collection_length);
+            frame.write_slice(&buffered[start..start + length]);
+            self.cursor += start + length;
+            return Ok(Some(frame));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::buffer::Buffer;
+    use std::io::Cursor;
+    use crate::framing::{retain_frames, write_control_frame, write_packet_frame, FrameDecoder, FrameError, FrameKind, ReadState};
+    use crate::request::RequestId;
+
+    fn packet(bytes: &[u8]) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.write_slice(bytes);
+        buf
+    }
+
+    fn body(mut frame: Buffer) -> Vec<u8> {
+        assert_eq!(FrameKind::read(&mut frame).unwrap(), FrameKind::Packet);
+        frame.read_slice(frame.remaining()).unwrap().to_vec()
+    }
+
+    #[test]
+    pub fn test_reassembles_partial_frames() {
+        let mut encoded = vec![];
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[0u8; 300]));
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[1, 2, 3]));
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&encoded[..1]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&encoded[1..100]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&encoded[100..]);
+
+        assert_eq!(body(decoder.next_frame().unwrap().unwrap()), [0u8; 300]);
+        assert_eq!(body(decoder.next_frame().unwrap().unwrap()), [1, 2, 3]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    pub fn test_rejects_oversized_frames() {
+        let mut encoded = vec![];
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[0u8; 64]));
+
+        let mut decoder = FrameDecoder::new(16);
+        decoder.push(&encoded[..2]);
+        assert_eq!(decoder.next_frame().unwrap_err(), FrameError::TooLarge { length: 65, max: 16 });
+    }
+
+    #[test]
+    pub fn test_read_from_stops_when_full() {
+        let mut encoded = vec![];
+        for _ in 0..1000 {
+            write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[1, 2, 3, 4]));
+        }
+
+        let mut reader = Cursor::new(encoded);
+        let mut decoder = FrameDecoder::new(16);
+        let mut frames = 0;
+        loop {
+            let state = decoder.read_from(&mut reader).unwrap();
+            assert!(decoder.buffered() < 16 + 5 + 4096);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                assert_eq!(body(frame), [1, 2, 3, 4]);
+                frames += 1;
+            }
+            if state == ReadState::Closed {
+                break;
+            }
+        }
+        assert_eq!(frames, 1000);
+        assert_eq!(decoder.buffered(), 0);
+
+        let mut unbounded = FrameDecoder::new(usize::MAX);
+        assert!(!unbounded.is_full());
+        assert_eq!(unbounded.read_from(&mut Cursor::new([1, 0, 7])).unwrap(), ReadState::Closed);
+        assert_eq!(unbounded.buffered(), 3);
+    }
+
+    #[test]
+    pub fn test_rejects_malformed_length() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&[0xFF; 6]);
+        assert_eq!(decoder.next_frame().unwrap_err(), FrameError::MalformedLength);
+    }
+
+    #[test]
+    pub fn test_frame_kinds() {
+        let mut encoded = vec![];
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[7]));
+        write_packet_frame(&mut encoded, FrameKind::Request(RequestId::new(300)), &packet(&[8]));
+        write_packet_frame(&mut encoded, FrameKind::Response(RequestId::new(300)), &packet(&[9]));
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&encoded);
+        for (kind, body) in [
+            (FrameKind::Packet, 7),
+            (FrameKind::Request(RequestId::new(300)), 8),
+            (FrameKind::Response(RequestId::new(300)), 9),
+        ] {
+            let mut frame = decoder.next_frame().unwrap().unwrap();
+            assert_eq!(FrameKind::read(&mut frame).unwrap(), kind);
+            assert_eq!(frame.read_u8().unwrap(), body);
+        }
+
+        let mut frame = packet(&[42]);
+        assert!(FrameKind::read(&mut frame).is_err());
+    }
+
+    #[test]
+    pub fn test_retain_frames() {
+        let mut encoded = vec![];
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[1, 2]));
+        write_packet_frame(&mut encoded, FrameKind::Request(RequestId::new(4)), &packet(&[3]));
+        write_control_frame(&mut encoded, FrameKind::Ping(5));
+        write_packet_frame(&mut encoded, FrameKind::Packet, &packet(&[6]));
+        retain_frames(&mut encoded, |kind| kind == FrameKind::Packet);
+
+        let mut expected = vec![];
+        write_packet_frame(&mut expected, FrameKind::Packet, &packet(&[1, 2]));
+        write_packet_frame(&mut expected, FrameKind::Packet, &packet(&[6]));
+        assert_eq!(encoded, expected);
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl KeepAlive {
+    pub fn new(interval: Duration, timeout: Duration) -> KeepAlive {
+        KeepAlive { interval, timeout }
+    }
+
+    pub(crate) fn poll_timeout(keepalive: Option<KeepAlive>) -> Option<Duration> {
+        keepalive.map(|keepalive| keepalive.interval.min(keepalive.timeout) / 2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HeartbeatAction {
+    Idle,
+    Ping(u32),
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    keepalive: Option<KeepAlive>,
+    last_received: Instant,
+    last_ping: Instant,
+    outstanding: Option<(u32, Instant)>,
+    next_nonce: u32,
+    rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(keepalive: Option<KeepAlive>, now: Instant) -> Heartbeat {
+        Heartbeat {
+            keepalive,
+            last_received: now,
+            last_ping: now,
+            outstanding: None,
+            next_nonce: 0,
+            rtt: None,
+        }
+    }
+
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub(crate) fn received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    pub(crate) fn pong(&mut self, nonce: u32, now: Instant) {
+        if let Some((expected, sent)) = self.outstanding {
+            if expected == nonce {
+                self.rtt = Some(now.saturating_duration_since(sent));
+                self.outstanding = None;
+            }
+        }
+    }
+
+    /// The earliest time `poll` could do something, or `None` without a keepalive.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let keepalive = self.keepalive?;
+        let timeout = self.last_received + keepalive.timeout;
+        Some(match self.outstanding {
+            Some(_) => timeout,
+            None => timeout.min(self.last_ping + keepalive.interval),
+        })
+    }
+
+    pub(crate) fn poll(&mut self, now: Instant) -> HeartbeatAction {
+        let Some(keepalive) = self.keepalive else {
+            return HeartbeatAction::Idle;
+        };
+        if now.saturating_duration_since(self.last_received) >= keepalive.timeout {
+            return HeartbeatAction::TimedOut;
+        }
+        if self.outstanding.is_some() || now.saturating_duration_since(self.last_ping) < keepalive.interval {
+            return HeartbeatAction::Idle;
+        }
+
+        let nonce = self.next_nonce;
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        self.last_ping = now;
+        self.outstanding = Some((nonce, now));
+        HeartbeatAction::Ping(nonce)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::{Duration, Instant};
+    use crate::keepalive::{Heartbeat, HeartbeatAction, KeepAlive};
+
+    #[test]
+    pub fn test_heartbeat_pings_and_measures_rtt() {
+        let start = Instant::now();
+        let keepalive = KeepAlive::new(Duration::from_secs(5), Duration::from_secs(15));
+        let mut heartbeat = Heartbeat::new(Some(keepalive), start);
+
+        assert_eq!(heartbeat.next_deadline(), Some(start + Duration::from_secs(5)));
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(1)), HeartbeatAction::Idle);
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(5)), HeartbeatAction::Ping(0));
+        assert_eq!(heartbeat.next_deadline(), Some(start + Duration::from_secs(15)));
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(11)), HeartbeatAction::Idle);
+
+        heartbeat.received(start + Duration::from_secs(12));
+        heartbeat.pong(0, start + Duration::from_secs(12));
+        assert_eq!(heartbeat.rtt(), Some(Duration::from_secs(7)));
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(12)), HeartbeatAction::Ping(1));
+    }
+
+    #[test]
+    pub fn test_heartbeat_times_out() {
+        let start = Instant::now();
+        let keepalive = KeepAlive::new(Duration::from_secs(5), Duration::from_secs(15));
+        let mut heartbeat = Heartbeat::new(Some(keepalive), start);
+        heartbeat.received(start + Duration::from_secs(10));
+        assert_ne!(heartbeat.poll(start + Duration::from_secs(20)), HeartbeatAction::TimedOut);
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(25)), HeartbeatAction::TimedOut);
+
+        let mut disabled = Heartbeat::new(None, start);
+        assert_eq!(disabled.poll(start + Duration::from_secs(3600)), HeartbeatAction::Idle);
+        assert_eq!(disabled.next_deadline(), None);
+    }
+}
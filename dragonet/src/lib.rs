@@ -1,8 +1,17 @@
 #![allow(unused)]
 
+extern crate self as dragonet;
+
 pub mod protocol;
 pub mod server;
 pub mod buffer;
 pub mod client;
+pub mod framing;
+pub mod disconnect;
+pub mod keepalive;
+pub mod reconnect;
+pub mod request;
+pub mod transport;
+pub mod testing;
 
 pub use dragonet_macros as _;
\ No newline at end of file
@@ -1,14 +1,15 @@
-use std::fmt::Debug;
-use crate::buffer::Buffer;
+use std::fmt::{Debug, Display, Formatter};
+use crate::buffer::{Buffer, BufferError};
+use crate::framing::FrameError;
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketDirection {
     Clientbound,
     Serverbound
 }
 
-pub trait PacketState: Clone {
-    fn get_state_by_id(id: u8) -> Self; 
+pub trait PacketState: Clone + PartialEq {
+    fn get_state_by_id(id: u8) -> Self;
 }
 
 #[derive(Clone, Copy)]
@@ -18,8 +19,223 @@ pub struct PacketMetadata<S: PacketState> {
     pub direction: PacketDirection
 }
 
-pub trait Protocol<S: PacketState>: Debug {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolViolation {
+    UnknownPacket { id: u32 },
+    WrongState { id: u32 },
+    WrongDirection { id: u32 },
+}
+
+impl Display for ProtocolViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolViolation::UnknownPacket { id } => write!(f, "packet id {} is unknown in the current state", id),
+            ProtocolViolation::WrongState { id } => write!(f, "packet id {} is not valid in the current state", id),
+            ProtocolViolation::WrongDirection { id } => write!(f, "packet id {} was sent in the wrong direction", id),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolViolation {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ViolationPolicy {
+    Drop,
+    #[default]
+    Disconnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Buffer(BufferError),
+    Frame(FrameError),
+    UnknownPacket(u32),
+    Violation(ProtocolViolation),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Buffer(err) => write!(f, "malformed packet: {}", err),
+            DecodeError::Frame(err) => write!(f, "malformed frame: {}", err),
+            DecodeError::UnknownPacket(id) => write!(f, "unknown packet id {}", id),
+            DecodeError::Violation(violation) => write!(f, "protocol violation: {}", violation),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<BufferError> for DecodeError {
+    fn from(value: BufferError) -> Self {
+        DecodeError::Buffer(value)
+    }
+}
+
+impl From<FrameError> for DecodeError {
+    fn from(value: FrameError) -> Self {
+        DecodeError::Frame(value)
+    }
+}
+
+pub trait Protocol<S: PacketState>: Debug + Sized {
     fn encode(&self) -> Buffer;
-    fn decode(buf: &mut Buffer, meta: &PacketMetadata<S>) -> Self;
+    fn decode(buf: &mut Buffer, meta: &PacketMetadata<S>) -> Result<Self, DecodeError>;
     fn metadata(&self) -> PacketMetadata<S>;
 }
+
+pub trait PacketKind<S: PacketState, T: Protocol<S>> {
+    type Payload<'a> where T: 'a;
+    type Owned;
+
+    fn payload(packet: &T) -> Option<Self::Payload<'_>>;
+    fn into_owned(packet: T) -> Result<Self::Owned, T>;
+    fn from_owned(owned: Self::Owned) -> T;
+}
+
+pub trait RequestKind<S: PacketState, T: Protocol<S>>: PacketKind<S, T> {
+    type Response: PacketKind<S, T>;
+}
+
+pub(crate) fn decode_packet<S, T>(frame: &mut Buffer, state: S, direction: PacketDirection) -> Result<T, DecodeError>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    let id = frame.read_var_int()?;
+    let meta = PacketMetadata {
+        id: u32::try_from(id).map_err(|_| BufferError::OutOfRange(id))?,
+        state: state.clone(),
+        direction,
+    };
+    let packet = match T::decode(frame, &meta) {
+        Err(DecodeError::UnknownPacket(id)) => {
+            return Err(DecodeError::Violation(ProtocolViolation::UnknownPacket { id }));
+        }
+        decoded => decoded?,
+    };
+    check_packet(&packet, &state, direction).map_err(DecodeError::Violation)?;
+    Ok(packet)
+}
+
+pub(crate) fn check_packet<S, T>(packet: &T, state: &S, direction: PacketDirection) -> Result<(), ProtocolViolation>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    let meta = packet.metadata();
+    if meta.direction != direction {
+        return Err(ProtocolViolation::WrongDirection { id: meta.id });
+    }
+    if meta.state != *state {
+        return Err(ProtocolViolation::WrongState { id: meta.id });
+    }
+    Ok(())
+}
+#[cfg(test)]
+pub mod tests {
+    use dragonet_macros::protocol;
+    use crate::buffer::{Buffer, BufferError};
+    use crate::protocol::{check_packet, decode_packet, DecodeError, PacketDirection, PacketKind, PacketState, Protocol, ProtocolViolation, RequestKind};
+
+    protocol! {
+        state Login {
+            serverbound packet Hello(String);
+        }
+        state Play {
+            clientbound packet Chat(String);
+            serverbound packet Move(i32, i32);
+            serverbound packet ListPlayers -> PlayerList;
+            clientbound packet PlayerList(Vec<String>);
+        }
+    }
+
+    #[test]
+    pub fn test_protocol_round_trip() {
+        let login = (ProtocolState::Login, PacketDirection::Serverbound);
+        let (play_in, play_out) = (
+            (ProtocolState::Play, PacketDirection::Serverbound),
+            (ProtocolState::Play, PacketDirection::Clientbound),
+        );
+        for (packet, (state, direction), id) in [
+            (Packets::C2SHello("hi".to_string()), login, 0),
+            (Packets::S2CChat("hello".to_string()), play_out, 0),
+            (Packets::C2SMove(-1, 2), play_in, 0),
+            (Packets::C2SListPlayers, play_in, 1),
+            (Packets::S2CPlayerList(vec!["a".to_string(), "b".to_string()]), play_out, 1),
+        ] {
+            let meta = packet.metadata();
+            assert_eq!((meta.state, meta.direction, meta.id), (state, direction, id));
+            let decoded: Packets = decode_packet(&mut packet.encode(), state, direction).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", packet));
+        }
+
+        assert_eq!(ProtocolState::get_state_by_id(0), ProtocolState::Login);
+        assert_eq!(ProtocolState::get_state_by_id(1), ProtocolState::Play);
+    }
+
+    #[test]
+    pub fn test_decode_checks_state() {
+        let mut frame = Packets::C2SMove(1, 2).encode();
+        let packet: Packets = decode_packet(&mut frame, ProtocolState::Play, PacketDirection::Serverbound).unwrap();
+        assert!(matches!(packet, Packets::C2SMove(1, 2)));
+
+        let mut frame = Buffer::new();
+        frame.write_var_int(5);
+        let err = decode_packet::<ProtocolState, Packets>(&mut frame, ProtocolState::Login, PacketDirection::Serverbound);
+        assert_eq!(err.unwrap_err(), DecodeError::Violation(ProtocolViolation::UnknownPacket { id: 5 }));
+
+        for id in [-1, 1 << 32] {
+            let mut frame = Buffer::new();
+            frame.write_var_int(id);
+            let err = decode_packet::<ProtocolState, Packets>(&mut frame, ProtocolState::Play, PacketDirection::Serverbound);
+            assert_eq!(err.unwrap_err(), DecodeError::Buffer(BufferError::OutOfRange(id)));
+        }
+    }
+
+    #[test]
+    pub fn test_check_packet() {
+        let chat = Packets::S2CChat("hi".to_string());
+        assert!(check_packet(&chat, &ProtocolState::Play, PacketDirection::Clientbound).is_ok());
+        assert_eq!(
+            check_packet(&chat, &ProtocolState::Login, PacketDirection::Clientbound),
+            Err(ProtocolViolation::WrongState { id: 0 }),
+        );
+        assert_eq!(
+            check_packet(&chat, &ProtocolState::Play, PacketDirection::Serverbound),
+            Err(ProtocolViolation::WrongDirection { id: 0 }),
+        );
+    }
+
+    #[test]
+    pub fn test_packet_kind_payload() {
+        let moved = Packets::C2SMove(3, 4);
+        assert_eq!(packets::C2SMove::payload(&moved), Some((&3, &4)));
+        assert!(packets::C2SHello::payload(&moved).is_none());
+
+        let hello = Packets::C2SHello("hi".to_string());
+        assert_eq!(packets::C2SHello::payload(&hello).map(String::as_str), Some("hi"));
+    }
+
+    #[test]
+    pub fn test_request_declares_response() {
+        fn response_of<P: RequestKind<ProtocolState, Packets>>(packet: &Packets) -> bool {
+            P::Response::payload(packet).is_some()
+        }
+
+        assert!(response_of::<packets::C2SListPlayers>(&Packets::S2CPlayerList(vec![])));
+        assert!(!response_of::<packets::C2SListPlayers>(&Packets::S2CChat("hi".to_string())));
+    }
+
+    #[test]
+    pub fn test_packet_kind_owned() {
+        assert!(matches!(packets::C2SMove::from_owned((3, 4)), Packets::C2SMove(3, 4)));
+        assert!(matches!(packets::C2SListPlayers::from_owned(()), Packets::C2SListPlayers));
+        assert_eq!(packets::C2SMove::into_owned(Packets::C2SMove(3, 4)).ok(), Some((3, 4)));
+
+        let list = Packets::S2CPlayerList(vec!["a".to_string()]);
+        let players: Vec<String> = <packets::C2SListPlayers as RequestKind<_, _>>::Response::into_owned(list).unwrap();
+        assert_eq!(players, ["a"]);
+        assert!(packets::S2CPlayerList::into_owned(Packets::S2CChat("hi".to_string())).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuedPackets {
+    /// Resends packets that were still queued when the connection dropped. Queued requests
+    /// are never resent, since they have already failed with `Disconnected`.
+    #[default]
+    Replay,
+    Discard,
+}
+
+/// Delays grow as `base_delay * 2^n` up to `max_delay`. `jitter` is the fraction
+/// (0.0 to 1.0) of each delay that may be randomly shaved off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: Option<u32>,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub queued_packets: QueuedPackets,
+}
+
+impl ReconnectPolicy {
+    pub fn new(base_delay: Duration, max_delay: Duration) -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: None,
+            base_delay,
+            max_delay,
+            jitter: 0.0,
+            queued_packets: QueuedPackets::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    policy: ReconnectPolicy,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(policy: ReconnectPolicy) -> Backoff {
+        Backoff { policy, attempt: 0 }
+    }
+
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub(crate) fn next_delay(&mut self, random: f64) -> Option<Duration> {
+        if self.policy.max_attempts.is_some_and(|max| self.attempt >= max) {
+            return None;
+        }
+        let delay = self.policy.base_delay
+            .saturating_mul(1 << self.attempt.min(31))
+            .min(self.policy.max_delay);
+        self.attempt += 1;
+        let jitter = self.policy.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0);
+        Some(delay.mul_f64(1.0 - jitter))
+    }
+}
+
+pub(crate) fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+    use crate::reconnect::{random_unit, Backoff, ReconnectPolicy};
+
+    #[test]
+    pub fn test_backoff_grows_and_caps() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(5),
+            ..ReconnectPolicy::new(Duration::from_millis(100), Duration::from_millis(500))
+        };
+        let mut backoff = Backoff::new(policy);
+        let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay(0.0)).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+        assert_eq!(backoff.attempt(), 5);
+    }
+
+    #[test]
+    pub fn test_backoff_jitter() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..ReconnectPolicy::new(Duration::from_millis(100), Duration::from_secs(1))
+        };
+        let mut backoff = Backoff::new(policy);
+        assert_eq!(backoff.next_delay(1.0), Some(Duration::from_millis(50)));
+        assert_eq!(backoff.next_delay(0.0), Some(Duration::from_millis(200)));
+
+        let random = random_unit();
+        assert!((0.0..1.0).contains(&random));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use crate::client::Client;
+use crate::protocol::{PacketKind, PacketState, Protocol, ProtocolViolation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId(u32);
+
+impl RequestId {
+    pub fn new(id: u32) -> RequestId {
+        RequestId(id)
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    Violation(ProtocolViolation),
+    UnexpectedResponse,
+    Timeout,
+    Disconnected,
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Violation(violation) => write!(f, "request rejected: {}", violation),
+            RequestError::UnexpectedResponse => write!(f, "response does not match the declared response kind"),
+            RequestError::Timeout => write!(f, "request timed out"),
+            RequestError::Disconnected => write!(f, "connection closed before a response arrived"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<ProtocolViolation> for RequestError {
+    fn from(value: ProtocolViolation) -> Self {
+        RequestError::Violation(value)
+    }
+}
+
+struct SlotState<T> {
+    result: Option<Result<T, RequestError>>,
+    waker: Option<Waker>,
+}
+
+pub(crate) struct ResponseSlot<T> {
+    state: Mutex<SlotState<T>>,
+    condvar: Condvar,
+    accepts: fn(&T) -> bool,
+}
+
+impl<T> ResponseSlot<T> {
+    pub(crate) fn new(accepts: fn(&T) -> bool) -> Arc<ResponseSlot<T>> {
+        Arc::new(ResponseSlot {
+            state: Mutex::new(SlotState { result: None, waker: None }),
+            condvar: Condvar::new(),
+            accepts,
+        })
+    }
+
+    pub(crate) fn respond(&self, packet: T) {
+        if (self.accepts)(&packet) {
+            self.complete(Ok(packet));
+        } else {
+            self.complete(Err(RequestError::UnexpectedResponse));
+        }
+    }
+
+    pub(crate) fn complete(&self, result: Result<T, RequestError>) {
+        let mut state = self.state.lock().unwrap();
+        if state.result.is_some() {
+            return;
+        }
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.condvar.notify_all();
+    }
+}
+
+pub struct PendingResponse<S, T, R>
+where
+    S: PacketState,
+    T: Protocol<S>,
+    R: PacketKind<S, T>,
+{
+    id: RequestId,
+    slot: Arc<ResponseSlot<T>>,
+    client: Weak<Mutex<Client<S, T>>>,
+    _response: PhantomData<fn() -> R>,
+}
+
+impl<S, T, R> PendingResponse<S, T, R>
+where
+    S: PacketState,
+    T: Protocol<S>,
+    R: PacketKind<S, T>,
+{
+    pub(crate) fn new(id: RequestId, slot: Arc<ResponseSlot<T>>, client: Weak<Mutex<Client<S, T>>>) -> Self {
+        PendingResponse { id, slot, client, _response: PhantomData }
+    }
+
+    fn convert(result: Result<T, RequestError>) -> Result<R::Owned, RequestError> {
+        result.and_then(|packet| R::into_owned(packet).map_err(|_| RequestError::UnexpectedResponse))
+    }
+
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// Blocks the calling thread until the response arrives. Never call this from a
+    /// packet handler, since handlers run on the thread that receives the response.
+    pub fn wait(self, timeout: Duration) -> Result<R::Owned, RequestError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.slot.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return Self::convert(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RequestError::Timeout);
+            }
+            state = self.slot.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl<S, T, R> Future for PendingResponse<S, T, R>
+where
+    S: PacketState,
+    T: Protocol<S>,
+    R: PacketKind<S, T>,
+{
+    type Output = Result<R::Owned, RequestError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(Self::convert(result)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S, T, R> Drop for PendingResponse<S, T, R>
+where
+    S: PacketState,
+    T: Protocol<S>,
+    R: PacketKind<S, T>,
+{
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            client.lock().unwrap().pending.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::request::{RequestError, ResponseSlot};
+
+    #[test]
+    pub fn test_response_slot_checks_kind() {
+        let slot = ResponseSlot::new(|value: &u32| *value < 10);
+        slot.respond(3);
+        slot.respond(4);
+        assert_eq!(slot.state.lock().unwrap().result, Some(Ok(3)));
+
+        let slot = ResponseSlot::new(|value: &u32| *value < 10);
+        slot.respond(30);
+        assert_eq!(slot.state.lock().unwrap().result, Some(Err(RequestError::UnexpectedResponse)));
+    }
+}
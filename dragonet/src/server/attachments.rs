@@ -0,0 +1,56 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct Attachments {
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Attachments {
+    pub(crate) fn insert<A: Any + Send>(&mut self, value: A) -> Option<A> {
+        self.values.insert(TypeId::of::<A>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub(crate) fn get<A: Any + Send>(&self) -> Option<&A> {
+        self.values.get(&TypeId::of::<A>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub(crate) fn get_mut<A: Any + Send>(&mut self) -> Option<&mut A> {
+        self.values.get_mut(&TypeId::of::<A>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub(crate) fn remove<A: Any + Send>(&mut self) -> Option<A> {
+        self.values.remove(&TypeId::of::<A>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::server::attachments::Attachments;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Username(String);
+
+    #[test]
+    pub fn test_attachments_are_keyed_by_type() {
+        let mut attachments = Attachments::default();
+        assert!(attachments.insert(Username("alex".to_string())).is_none());
+        assert!(attachments.insert(3u32).is_none());
+
+        assert_eq!(attachments.get::<Username>(), Some(&Username("alex".to_string())));
+        *attachments.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(attachments.get::<u32>(), Some(&4));
+        assert!(attachments.get::<u64>().is_none());
+
+        let previous = attachments.insert(Username("sam".to_string()));
+        assert_eq!(previous, Some(Username("alex".to_string())));
+        assert_eq!(attachments.remove::<Username>(), Some(Username("sam".to_string())));
+        assert!(attachments.get::<Username>().is_none());
+    }
+}
@@ -1,15 +1,40 @@
+use std::io;
+use std::io::ErrorKind::{Interrupted, WouldBlock};
+use std::io::Write;
 use std::marker::PhantomData;
-use std::net::TcpStream;
-use crate::protocol::{PacketState, Protocol};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use mio::Waker;
+use crate::disconnect::DisconnectReason;
+use crate::framing::{write_control_frame, write_packet_frame, FrameDecoder, FrameKind, ReadState};
+use crate::keepalive::Heartbeat;
+use crate::protocol::{check_packet, PacketDirection, PacketState, Protocol, ProtocolViolation};
+use crate::request::RequestId;
+use crate::server::attachments::Attachments;
+use crate::server::{ConnectionId, Server};
+use crate::transport::{Address, Stream};
 
 pub struct ServerConnection<S, T>
 where
     S: PacketState,
     T: Protocol<S>,
 {
-    pub(crate) stream: TcpStream,
-    pub(crate) packet_queue: Vec<T>,
+    pub(crate) id: ConnectionId,
+    pub(crate) stream: Stream,
+    pub(crate) peer_addr: Address,
+    pub(crate) local_addr: Address,
+    pub(crate) packet_queue: Vec<Arc<[u8]>>,
     pub(crate) state: Option<S>,
+    pub(crate) decoder: FrameDecoder,
+    pub(crate) write_buffer: Vec<u8>,
+    pub(crate) waker: Arc<Waker>,
+    pub(crate) pending_flush: Arc<Mutex<HashSet<ConnectionId>>>,
+    pub(crate) server: Weak<Mutex<Server<S, T>>>,
+    pub(crate) disconnect: Option<DisconnectReason>,
+    pub(crate) attachments: Attachments,
+    pub(crate) current_request: Option<RequestId>,
+    pub(crate) heartbeat: Heartbeat,
     pub(crate) _phantom: PhantomData<(S, T)>,
 }
 
@@ -18,14 +43,123 @@ where
     S: PacketState,
     T: Protocol<S>,
 {
+    pub(crate) fn new(
+        id: ConnectionId,
+        stream: Stream,
+        peer_addr: Address,
+        local_addr: Address,
+        waker: Arc<Waker>,
+        config: &Server<S, T>,
+        server: Weak<Mutex<Server<S, T>>>,
+    ) -> ServerConnection<S, T> {
+        ServerConnection {
+            id,
+            stream,
+            peer_addr,
+            local_addr,
+            packet_queue: Vec::new(),
+            state: None,
+            decoder: config.frame_decoder(),
+            write_buffer: Vec::new(),
+            waker,
+            pending_flush: config.pending_flush.clone(),
+            server,
+            disconnect: None,
+            attachments: Attachments::default(),
+            current_request: None,
+            heartbeat: Heartbeat::new(config.keepalive, Instant::now()),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn encode_frame(kind: FrameKind, packet: &T) -> Arc<[u8]> {
+        let mut frame = Vec::new();
+        write_packet_frame(&mut frame, kind, &packet.encode());
+        Arc::from(frame)
+    }
+
+    pub(crate) fn queue_control(&mut self, kind: FrameKind) {
+        let mut frame = Vec::new();
+        write_control_frame(&mut frame, kind);
+        self.queue_frame(Arc::from(frame));
+    }
+
+    pub(crate) fn handle_control(&mut self, kind: FrameKind, now: Instant) -> bool {
+        self.heartbeat.received(now);
+        match kind {
+            FrameKind::Ping(nonce) => self.queue_control(FrameKind::Pong(nonce)),
+            FrameKind::Pong(nonce) => self.heartbeat.pong(nonce, now),
+            _ => return false,
+        }
+        true
+    }
+
+    pub(crate) fn queue_frame(&mut self, frame: Arc<[u8]>) {
+        self.packet_queue.push(frame);
+        self.request_flush();
+    }
+
+    /// Asks the event loop to flush this connection, and close it if it is disconnecting.
+    pub(crate) fn request_flush(&self) {
+        self.pending_flush.lock().unwrap().insert(self.id);
+        let _ = self.waker.wake();
+    }
+
+    pub(crate) fn read_stream(&mut self) -> io::Result<ReadState> {
+        self.decoder.read_from(&mut self.stream)
+    }
+
+    pub(crate) fn is_drained(&self) -> bool {
+        self.packet_queue.is_empty() && self.write_buffer.is_empty()
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        for frame in self.packet_queue.drain(..) {
+            self.write_buffer.extend_from_slice(&frame);
+        }
+
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.write_buffer.drain(..written);
+                }
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) if err.kind() == Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn current_state(&self) -> S {
+        self.state.clone().unwrap_or_else(|| S::get_state_by_id(0))
+    }
+
+    pub(crate) fn accepts(&self, packet: &T) -> Result<(), ProtocolViolation> {
+        check_packet(packet, &self.current_state(), PacketDirection::Clientbound)
+    }
+
     pub fn set_state(&mut self, state: S) -> &mut ServerConnection<S, T> {
         self.state = Some(state);
         self
     }
 
-    pub fn send_packet(&mut self, packet: T) -> &mut ServerConnection<S, T> {
-        // TODO: write to connection
-        self.packet_queue.push(packet);
+    pub fn disconnect(&mut self, reason: DisconnectReason) -> &mut ServerConnection<S, T> {
+        self.disconnect.get_or_insert(reason);
+        self.request_flush();
         self
     }
+
+    pub fn send_packet(&mut self, packet: T) -> Result<(), ProtocolViolation> {
+        self.accepts(&packet)?;
+        self.queue_frame(Self::encode_frame(FrameKind::Packet, &packet));
+        Ok(())
+    }
+
+    pub fn respond(&mut self, request: RequestId, packet: T) -> Result<(), ProtocolViolation> {
+        self.accepts(&packet)?;
+        self.queue_frame(Self::encode_frame(FrameKind::Response(request), &packet));
+        Ok(())
+    }
 }
\ No newline at end of file
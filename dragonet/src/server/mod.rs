@@ -1,36 +1,106 @@
+mod attachments;
 mod conn;
 mod refs;
+#[cfg(unix)]
+mod signals;
 
 use std::alloc::System;
-use std::any::Any;
-use std::collections::HashMap;
-use std::io::ErrorKind::{Interrupted, WouldBlock};
+use std::any::{Any, TypeId};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::ErrorKind::{ConnectionAborted, Interrupted, WouldBlock};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use dragonet_runtime::Runtime;
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
 use crate::buffer::Buffer;
-use crate::protocol::{PacketDirection, PacketMetadata, PacketState, Protocol};
+use crate::buffer::{DEFAULT_MAX_COLLECTION_LENGTH, DEFAULT_MAX_STRING_LENGTH};
+use crate::disconnect::DisconnectReason;
+use crate::framing::{FrameDecoder, FrameKind, ReadState, DEFAULT_MAX_FRAME_SIZE};
+use crate::keepalive::{HeartbeatAction, KeepAlive};
+use crate::protocol::{decode_packet, DecodeError, PacketDirection, PacketKind, PacketMetadata, PacketState, Protocol, ProtocolViolation, RequestKind, ViolationPolicy};
+use crate::request::RequestId;
 use crate::server::conn::ServerConnection;
-use crate::server::refs::{ConnectionRef, ServerRef};
+use crate::transport::{Address, Listener, Stream};
 
-static mut CONNECTION_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+pub use crate::server::refs::{ConnectionRef, ServerRef};
 
-type ServerPacketEvent<S, T> = fn(ConnectionRef<S, T>, &T);
+static CONNECTION_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(usize);
+
+impl ConnectionId {
+    fn next() -> ConnectionId {
+        ConnectionId(CONNECTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0
+    }
+
+    fn token(&self) -> Token {
+        Token(self.0)
+    }
+}
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+const WAKER: Token = Token(usize::MAX);
+const FIRST_LISTENER: usize = usize::MAX - 1;
+
+struct ShutdownRequest<T> {
+    deadline: Instant,
+    farewell: Option<T>,
+}
+
+type ServerStartupEvent<S, T> = Arc<dyn Fn(ServerRef<S, T>) + Send + Sync>;
+type ServerConnectionEvent<S, T> = Arc<dyn Fn(ConnectionRef<S, T>) + Send + Sync>;
+type ServerPacketEvent<S, T> = Arc<dyn Fn(ConnectionRef<S, T>, &T) + Send + Sync>;
+type ServerRoute<S, T> = Arc<dyn Fn(ConnectionRef<S, T>, &T) -> bool + Send + Sync>;
+type ServerViolationEvent<S, T> = Arc<dyn Fn(ConnectionRef<S, T>, &ProtocolViolation) + Send + Sync>;
+type ServerDisconnectEvent<S, T> = Arc<dyn Fn(ConnectionRef<S, T>, &DisconnectReason) + Send + Sync>;
 
 pub struct Server<S, T>
 where
     S: PacketState,
     T: Protocol<S>,
 {
-    socket: Option<TcpListener>,
-    conn_events: Vec<fn(ConnectionRef<S, T>)>,
+    listeners: Vec<Listener>,
+    conn_events: Vec<ServerConnectionEvent<S, T>>,
     recv_events: Vec<ServerPacketEvent<S, T>>,
-    startup_events: Vec<fn(ServerRef<S, T>)>,
-    connections: HashMap<usize, Arc<Mutex<ServerConnection<S, T>>>>,
+    routes: Vec<ServerRoute<S, T>>,
+    fallback_event: Option<ServerPacketEvent<S, T>>,
+    disconnect_events: Vec<ServerDisconnectEvent<S, T>>,
+    violation_events: Vec<ServerViolationEvent<S, T>>,
+    violation_policy: ViolationPolicy,
+    startup_events: Vec<ServerStartupEvent<S, T>>,
+    shared_state: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    connections: HashMap<ConnectionId, Arc<Mutex<ServerConnection<S, T>>>>,
+    /// Connections with queued output or a pending disconnect.
+    pending_flush: Arc<Mutex<HashSet<ConnectionId>>>,
+    /// One heartbeat deadline per keepalive connection. Entries for closed connections are
+    /// dropped when they come due.
+    timers: BinaryHeap<Reverse<(Instant, ConnectionId)>>,
+    max_frame_size: usize,
+    max_string_length: usize,
+    max_collection_length: usize,
+    keepalive: Option<KeepAlive>,
+    waker: Option<Arc<Waker>>,
+    shutdown: Option<ShutdownRequest<T>>,
+    shutdown_signals: Option<Duration>,
     _phantom: PhantomData<(S, T)>,
 }
 
@@ -44,37 +114,869 @@ impl<S: PacketState, T: Protocol<S>> Default for Server<S, T> {
 impl<S: PacketState, T: Protocol<S>> Server<S, T> {
     pub fn new() -> Server<S, T> {
         Server {
-            socket: None,
+            listeners: Vec::new(),
             connections: HashMap::new(),
+            pending_flush: Arc::new(Mutex::new(HashSet::new())),
+            timers: BinaryHeap::new(),
             conn_events: Vec::new(),
             recv_events: Vec::new(),
+            routes: Vec::new(),
+            fallback_event: None,
+            disconnect_events: Vec::new(),
+            violation_events: Vec::new(),
+            violation_policy: ViolationPolicy::default(),
             startup_events: Vec::new(),
+            shared_state: HashMap::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_collection_length: DEFAULT_MAX_COLLECTION_LENGTH,
+            keepalive: None,
+            waker: None,
+            shutdown: None,
+            shutdown_signals: None,
             _phantom: PhantomData,
         }
     }
 
-    pub fn with_address(&mut self, addr: SocketAddrV4) -> &mut Server<S, T> {
-        self.socket = Some(TcpListener::bind(SocketAddr::V4(addr)).unwrap());
+    pub fn with_address(&mut self, addr: impl ToSocketAddrs) -> io::Result<&mut Server<S, T>> {
+        let mut listeners = Vec::new();
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            match Listener::bind_tcp(addr) {
+                Ok(listener) => listeners.push(listener),
+                Err(err) if err.kind() == io::ErrorKind::AddrNotAvailable => last_error = Some(err),
+                Err(err) => return Err(err),
+            }
+        }
+        if listeners.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "address resolved to no socket addresses")
+            }));
+        }
+        self.listeners.extend(listeners);
+        Ok(self)
+    }
+
+    /// Listens on a unix domain socket. The socket file is removed when the server stops.
+    #[cfg(unix)]
+    pub fn with_unix_path(&mut self, path: impl AsRef<Path>) -> io::Result<&mut Server<S, T>> {
+        self.listeners.push(Listener::bind_unix(path.as_ref())?);
+        Ok(self)
+    }
+
+    pub fn local_addrs(&self) -> io::Result<Vec<Address>> {
+        self.listeners.iter().map(Listener::local_addr).collect()
+    }
+
+    pub fn with_max_frame_size(&mut self, max_frame_size: usize) -> &mut Server<S, T> {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn with_max_string_length(&mut self, max_string_length: usize) -> &mut Server<S, T> {
+        self.max_string_length = max_string_length;
         self
     }
 
-    pub fn with_startup_event(&mut self, function: fn(ServerRef<S, T>)) -> &mut Server<S, T> {
-        self.startup_events.push(function);
+    pub fn with_max_collection_length(&mut self, max_collection_length: usize) -> &mut Server<S, T> {
+        self.max_collection_length = max_collection_length;
         self
     }
 
-    pub fn with_connection_event(&mut self, function: fn(ConnectionRef<S, T>)) -> &mut Server<S, T> {
-        self.conn_events.push(function);
+    pub fn with_keepalive(&mut self, interval: Duration, timeout: Duration) -> &mut Server<S, T> {
+        self.keepalive = Some(KeepAlive::new(interval, timeout));
         self
     }
 
-    pub fn with_packet_event(&mut self, function: ServerPacketEvent<S, T>) -> &mut Server<S, T> {
-        self.recv_events.push(function);
+    #[cfg(unix)]
+    pub fn with_shutdown_signals(&mut self, grace: Duration) -> &mut Server<S, T> {
+        self.shutdown_signals = Some(grace);
         self
     }
 
-    pub fn event_loop(mut self) {
+    pub fn with_violation_policy(&mut self, policy: ViolationPolicy) -> &mut Server<S, T> {
+        self.violation_policy = policy;
+        self
+    }
+
+    pub fn with_shared_state<U: Any + Send + Sync>(&mut self, state: U) -> &mut Server<S, T> {
+        self.shared_state.insert(TypeId::of::<U>(), Arc::new(state));
+        self
+    }
+
+    pub fn with_startup_event<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ServerRef<S, T>) + Send + Sync + 'static,
+    {
+        self.startup_events.push(Arc::new(function));
+        self
+    }
+
+    pub fn with_connection_event<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ConnectionRef<S, T>) + Send + Sync + 'static,
+    {
+        self.conn_events.push(Arc::new(function));
+        self
+    }
+
+    pub fn with_packet_event<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ConnectionRef<S, T>, &T) + Send + Sync + 'static,
+    {
+        self.recv_events.push(Arc::new(function));
+        self
+    }
+
+    pub fn on<P>(
+        &mut self,
+        function: impl for<'a> Fn(ConnectionRef<S, T>, P::Payload<'a>) + Send + Sync + 'static,
+    ) -> &mut Server<S, T>
+    where
+        P: PacketKind<S, T> + 'static,
+    {
+        self.routes.push(Arc::new(move |connection, packet| match P::payload(packet) {
+            Some(payload) => {
+                function(connection, payload);
+                true
+            }
+            None => false,
+        }));
+        self
+    }
+
+    pub fn on_request<P>(
+        &mut self,
+        function: impl for<'a> Fn(ConnectionRef<S, T>, RequestId, P::Payload<'a>) + Send + Sync + 'static,
+    ) -> &mut Server<S, T>
+    where
+        P: RequestKind<S, T> + 'static,
+    {
+        self.routes.push(Arc::new(move |connection, packet| {
+            let Some(request) = connection.request_id() else {
+                return false;
+            };
+            match P::payload(packet) {
+                Some(payload) => {
+                    function(connection, request, payload);
+                    true
+                }
+                None => false,
+            }
+        }));
+        self
+    }
+
+    pub fn with_fallback_event<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ConnectionRef<S, T>, &T) + Send + Sync + 'static,
+    {
+        self.fallback_event = Some(Arc::new(function));
+        self
+    }
+
+    pub fn with_disconnect_event<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ConnectionRef<S, T>, &DisconnectReason) + Send + Sync + 'static,
+    {
+        self.disconnect_events.push(Arc::new(function));
+        self
+    }
+
+    pub fn on_protocol_violation<F>(&mut self, function: F) -> &mut Server<S, T>
+    where
+        F: Fn(ConnectionRef<S, T>, &ProtocolViolation) + Send + Sync + 'static,
+    {
+        self.violation_events.push(Arc::new(function));
+        self
+    }
+
+    pub fn event_loop(mut self) -> io::Result<()> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "server has no address"));
+        }
+
+        let mut poll = Poll::new()?;
+        let mut listeners = Vec::new();
+        for (index, mut listener) in self.listeners.drain(..).enumerate() {
+            poll.registry().register(&mut listener, Token(FIRST_LISTENER - index), Interest::READABLE)?;
+            listeners.push(listener);
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+
+        #[cfg(unix)]
+        let signals = match self.shutdown_signals {
+            Some(grace) => Some((signals::SignalGuard::install(waker.clone())?, grace)),
+            None => None,
+        };
+        let server_ref = self.start(waker.clone());
+
+        let pending_flush = server_ref.lock().pending_flush.clone();
+        let mut draining: Option<Instant> = None;
+        let mut events = Events::with_capacity(128);
+        loop {
+            let next_timer = server_ref.lock().timers.peek().map(|Reverse((deadline, _))| *deadline);
+            let poll_timeout = [next_timer, draining].into_iter().flatten().min()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(err) = poll.poll(&mut events, poll_timeout) {
+                if err.kind() == Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => {}
+                    Token(token) if token <= FIRST_LISTENER && FIRST_LISTENER - token < listeners.len() => {
+                        let listener = &mut listeners[FIRST_LISTENER - token];
+                        Server::accept(&server_ref, listener, poll.registry(), &waker)?;
+                    }
+                    Token(id) => {
+                        if event.is_readable() {
+                            Server::read_connection(&server_ref, poll.registry(), ConnectionId(id));
+                        }
+                        if event.is_writable() {
+                            pending_flush.lock().unwrap().insert(ConnectionId(id));
+                        }
+                    }
+                }
+            }
+
+            #[cfg(unix)]
+            if let Some((signals, grace)) = &signals {
+                if signals.triggered() {
+                    server_ref.shutdown(*grace);
+                }
+            }
+            if Server::tick(&server_ref, poll.registry(), &mut listeners, &mut draining) {
+                return Ok(());
+            }
+        }
+    }
+
+    pub(crate) fn start(mut self, waker: Arc<Waker>) -> ServerRef<S, T> {
+        self.waker = Some(waker);
+        let server_ref = ServerRef { server: Arc::new(Mutex::new(self)) };
+        let startup_events = server_ref.lock().startup_events.clone();
+        for event in startup_events {
+            event(server_ref.clone());
+        }
+        server_ref
+    }
+
+    /// Everything the event loop does after handling I/O events. Returns true once a
+    /// shutdown has finished draining.
+    pub(crate) fn tick(
+        server: &ServerRef<S, T>,
+        registry: &Registry,
+        listeners: &mut Vec<Listener>,
+        draining: &mut Option<Instant>,
+    ) -> bool {
+        if draining.is_none() {
+            let request = server.lock().shutdown.as_mut()
+                .map(|request| (request.deadline, request.farewell.take()));
+            if let Some((deadline, farewell)) = request {
+                for mut listener in listeners.drain(..) {
+                    let _ = registry.deregister(&mut listener);
+                }
+                Server::begin_shutdown(server, farewell);
+                *draining = Some(deadline);
+            }
+        }
+
+        Server::heartbeat(server, registry);
+        Server::flush_connections(server, registry);
+
+        if let Some(deadline) = *draining {
+            if Instant::now() >= deadline {
+                let remaining: Vec<_> = server.lock().connections.keys().copied().collect();
+                for id in remaining {
+                    Server::close_connection(server, registry, id, DisconnectReason::Shutdown);
+                }
+            }
+            if server.lock().connections.is_empty() {
+                server.lock().waker = None;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn begin_shutdown(server: &ServerRef<S, T>, farewell: Option<T>) {
+        let farewell = farewell.map(|packet| (ServerConnection::encode_frame(FrameKind::Packet, &packet), packet));
+        for connection in server.lock().connections.values() {
+            let mut connection = connection.lock().unwrap();
+            if let Some((frame, packet)) = &farewell {
+                if connection.accepts(packet).is_ok() {
+                    connection.queue_frame(frame.clone());
+                }
+            }
+            connection.disconnect(DisconnectReason::Shutdown);
+        }
+    }
+
+    fn frame_decoder(&self) -> FrameDecoder {
+        let mut decoder = FrameDecoder::new(self.max_frame_size);
+        decoder.set_max_string_length(self.max_string_length);
+        decoder.set_max_collection_length(self.max_collection_length);
+        decoder
+    }
+
+    fn accept(
+        server: &ServerRef<S, T>,
+        listener: &mut Listener,
+        registry: &Registry,
+        waker: &Arc<Waker>,
+    ) -> io::Result<()> {
+        loop {
+            let (stream, peer_addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == WouldBlock => return Ok(()),
+                Err(err) if err.kind() == Interrupted || err.kind() == ConnectionAborted => continue,
+                Err(err) => return Err(err),
+            };
+
+            let local_addr = match stream.local_addr() {
+                Ok(local_addr) => local_addr,
+                Err(_) => continue,
+            };
+            Server::add_connection(server, stream, peer_addr, local_addr, registry, waker)?;
+        }
+    }
+
+    pub(crate) fn add_connection(
+        server: &ServerRef<S, T>,
+        mut stream: Stream,
+        peer_addr: Address,
+        local_addr: Address,
+        registry: &Registry,
+        waker: &Arc<Waker>,
+    ) -> io::Result<ConnectionId> {
+        let id = ConnectionId::next();
+        registry.register(&mut stream, id.token(), Interest::READABLE | Interest::WRITABLE)?;
+
+        let weak_server = Arc::downgrade(&server.server);
+        let (connection, conn_events) = {
+            let mut server = server.lock();
+            let connection = ServerConnection::new(id, stream, peer_addr, local_addr, waker.clone(), &server, weak_server);
+            if let Some(deadline) = connection.heartbeat.next_deadline() {
+                server.timers.push(Reverse((deadline, id)));
+            }
+            let connection = Arc::new(Mutex::new(connection));
+            server.connections.insert(id, connection.clone());
+            (connection, server.conn_events.clone())
+        };
+        for event in conn_events {
+            event(ConnectionRef { connection: connection.clone() });
+        }
+        Ok(id)
+    }
+
+    pub(crate) fn read_connection(server: &ServerRef<S, T>, registry: &Registry, id: ConnectionId) {
+        let Some(connection) = server.lock().connections.get(&id).cloned() else {
+            return;
+        };
+
+        loop {
+            let (read, mut reason) = match connection.lock().unwrap().read_stream() {
+                Ok(ReadState::Closed) => (ReadState::Closed, Some(DisconnectReason::RemoteClosed)),
+                Ok(read) => (read, None),
+                Err(err) => (ReadState::Closed, Some(DisconnectReason::Io(err))),
+            };
+            if let Some(decode_reason) = Server::decode_frames(server, &connection) {
+                reason = Some(decode_reason);
+            }
+
+            if let Some(reason) = reason {
+                Server::close_connection(server, registry, id, reason);
+                return;
+            }
+            if read != ReadState::Full || connection.lock().unwrap().disconnect.is_some() {
+                return;
+            }
+        }
+    }
+
+    fn decode_frames(server: &ServerRef<S, T>, connection: &Arc<Mutex<ServerConnection<S, T>>>) -> Option<DisconnectReason> {
+        let mut reason = None;
+        loop {
+            if connection.lock().unwrap().disconnect.is_some() {
+                break;
+            }
+
+            let frame = connection.lock().unwrap().decoder.next_frame();
+            match frame {
+                Ok(Some(mut frame)) => {
+                    let kind = match FrameKind::read(&mut frame) {
+                        Ok(kind) => kind,
+                        Err(err) => {
+                            reason = Some(DisconnectReason::Decode(err.into()));
+                            break;
+                        }
+                    };
+                    if connection.lock().unwrap().handle_control(kind, Instant::now()) {
+                        continue;
+                    }
+                    let state = connection.lock().unwrap().current_state();
+                    let packet = match decode_packet::<S, T>(&mut frame, state, PacketDirection::Serverbound) {
+                        Ok(packet) => packet,
+                        Err(DecodeError::Violation(violation)) => {
+                            let (policy, violation_events) = {
+                                let server = server.lock();
+                                (server.violation_policy, server.violation_events.clone())
+                            };
+                            for event in violation_events {
+                                event(ConnectionRef { connection: connection.clone() }, &violation);
+                            }
+                            if policy == ViolationPolicy::Disconnect {
+                                reason = Some(DisconnectReason::ProtocolViolation(violation));
+                                break;
+                            }
+                            continue;
+                        }
+                        Err(err) => {
+                            reason = Some(DisconnectReason::Decode(err));
+                            break;
+                        }
+                    };
 
+                    let request = match kind {
+                        FrameKind::Packet => None,
+                        FrameKind::Request(id) => Some(id),
+                        FrameKind::Response(_) | FrameKind::Ping(_) | FrameKind::Pong(_) => continue,
+                    };
+                    connection.lock().unwrap().current_request = request;
+                    Server::dispatch(server, connection, &packet);
+                    connection.lock().unwrap().current_request = None;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    reason = Some(DisconnectReason::Decode(err.into()));
+                    break;
+                }
+            }
+        }
+        reason
+    }
+
+    fn dispatch(server: &ServerRef<S, T>, connection: &Arc<Mutex<ServerConnection<S, T>>>, packet: &T) {
+        let (recv_events, routes, fallback_event) = {
+            let server = server.lock();
+            (server.recv_events.clone(), server.routes.clone(), server.fallback_event.clone())
+        };
+        for event in recv_events {
+            event(ConnectionRef { connection: connection.clone() }, packet);
+        }
+
+        let mut routed = false;
+        for route in routes {
+            routed |= route(ConnectionRef { connection: connection.clone() }, packet);
+        }
+        if let (false, Some(event)) = (routed, fallback_event) {
+            event(ConnectionRef { connection: connection.clone() }, packet);
+        }
+    }
+
+    /// Polls the heartbeats that have come due. Every connection with a keepalive has one
+    /// entry in `timers`, which is pushed back with its next deadline after each poll.
+    fn heartbeat(server: &ServerRef<S, T>, registry: &Registry) {
+        let now = Instant::now();
+        loop {
+            let (id, connection) = {
+                let mut server = server.lock();
+                let id = match server.timers.peek() {
+                    Some(&Reverse((deadline, id))) if deadline <= now => id,
+                    _ => return,
+                };
+                server.timers.pop();
+                let Some(connection) = server.connections.get(&id).cloned() else {
+                    continue;
+                };
+                (id, connection)
+            };
+
+            let (action, next_deadline) = {
+                let mut connection = connection.lock().unwrap();
+                let action = connection.heartbeat.poll(now);
+                if let HeartbeatAction::Ping(nonce) = action {
+                    connection.queue_control(FrameKind::Ping(nonce));
+                }
+                (action, connection.heartbeat.next_deadline())
+            };
+            match (action, next_deadline) {
+                (HeartbeatAction::TimedOut, _) => Server::close_connection(server, registry, id, DisconnectReason::Timeout),
+                (_, Some(deadline)) => server.lock().timers.push(Reverse((deadline, id))),
+                (_, None) => {}
+            }
+        }
+    }
+
+    /// Flushes the connections that queued output, became writable or started
+    /// disconnecting since the last call.
+    fn flush_connections(server: &ServerRef<S, T>, registry: &Registry) {
+        let connections: Vec<_> = {
+            let server = server.lock();
+            let pending = std::mem::take(&mut *server.pending_flush.lock().unwrap());
+            pending.into_iter()
+                .filter_map(|id| server.connections.get(&id).map(|connection| (id, connection.clone())))
+                .collect()
+        };
+
+        for (id, connection) in connections {
+            let reason = {
+                let mut connection = connection.lock().unwrap();
+                match connection.flush() {
+                    Ok(()) if connection.is_drained() => connection.disconnect.take(),
+                    Ok(()) => None,
+                    Err(err) => Some(DisconnectReason::Io(err)),
+                }
+            };
+            if let Some(reason) = reason {
+                Server::close_connection(server, registry, id, reason);
+            }
+        }
+    }
+
+    fn close_connection(server: &ServerRef<S, T>, registry: &Registry, id: ConnectionId, reason: DisconnectReason) {
+        let Some(connection) = server.lock().connections.remove(&id) else {
+            return;
+        };
+        {
+            let mut connection = connection.lock().unwrap();
+            let _ = registry.deregister(&mut connection.stream);
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+
+        let disconnect_events = server.lock().disconnect_events.clone();
+        for event in disconnect_events {
+            event(ConnectionRef { connection: connection.clone() }, &reason);
+        }
     }
 }
 
+#[cfg(test)]
+pub mod tests {
+    use std::io;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use crate::client::Client;
+    use crate::disconnect::DisconnectReason;
+    use crate::framing::{write_control_frame, write_packet_frame, FrameDecoder, FrameKind};
+    use crate::protocol::tests::{packets, Packets, ProtocolState};
+    use crate::protocol::{decode_packet, PacketDirection, Protocol, ViolationPolicy};
+    use crate::server::Server;
+    use crate::testing::Loopback;
+    use crate::transport::Address;
+
+    fn send(stream: &mut TcpStream, packet: Packets) {
+        let mut frame = Vec::new();
+        write_packet_frame(&mut frame, FrameKind::Packet, &packet.encode());
+        stream.write_all(&frame).unwrap();
+    }
+
+    #[test]
+    pub fn test_accepts_dispatches_and_flushes() {
+        const MESSAGES: usize = 4096;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let (connect_log, disconnect_log) = (log.clone(), log.clone());
+        server
+            .with_address("127.0.0.1:0")
+            .unwrap()
+            .with_connection_event(move |conn| connect_log.lock().unwrap().push(format!("accepted {}", conn.peer_addr())))
+            .on::<packets::C2SHello>(|conn, name| {
+                conn.set_state(ProtocolState::Play);
+                for index in 0..MESSAGES {
+                    conn.send_packet(Packets::S2CChat(format!("{} {} {}", name, index, "x".repeat(1024)))).unwrap();
+                }
+            })
+            .on::<packets::C2SMove>(|conn, _| conn.server().unwrap().shutdown(Duration::ZERO))
+            .with_disconnect_event(move |_, reason| disconnect_log.lock().unwrap().push(reason.to_string()));
+        let Address::Tcp(addr) = server.local_addrs().unwrap()[0] else {
+            panic!("expected a tcp address");
+        };
+        let server = std::thread::spawn(move || server.event_loop());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        send(&mut stream, Packets::C2SHello("alice".to_string()));
+        // Give the server time to fill the socket buffer, so the rest goes out on WRITABLE.
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut decoder = FrameDecoder::default();
+        let mut chunk = [0; 4096];
+        let mut received = 0;
+        while received < MESSAGES {
+            let read = stream.read(&mut chunk).unwrap();
+            assert_ne!(read, 0, "server closed after {} messages", received);
+            decoder.push(&chunk[..read]);
+            while let Some(mut frame) = decoder.next_frame().unwrap() {
+                assert_eq!(FrameKind::read(&mut frame).unwrap(), FrameKind::Packet);
+                let packet = decode_packet(&mut frame, ProtocolState::Play, PacketDirection::Clientbound).unwrap();
+                let Packets::S2CChat(message) = packet else {
+                    panic!("unexpected packet {:?}", packet);
+                };
+                assert!(message.starts_with(&format!("alice {} ", received)));
+                received += 1;
+            }
+        }
+
+        send(&mut stream, Packets::C2SMove(1, 2));
+        server.join().unwrap().unwrap();
+        let peer = stream.local_addr().unwrap();
+        assert_eq!(*log.lock().unwrap(), [format!("accepted {}", peer), "server shutting down".to_string()]);
+    }
+
+    #[test]
+    pub fn test_undecodable_frames_disconnect() {
+        fn disconnect_reason(bytes: &[u8]) -> String {
+            let reasons = Arc::new(Mutex::new(Vec::new()));
+            let mut server: Server<ProtocolState, Packets> = Server::new();
+            let disconnect_log = reasons.clone();
+            server
+                .with_address("127.0.0.1:0")
+                .unwrap()
+                .with_max_frame_size(64)
+                .with_disconnect_event(move |conn, reason| {
+                    assert!(matches!(reason, DisconnectReason::Decode(_)));
+                    disconnect_log.lock().unwrap().push(reason.to_string());
+                    conn.server().unwrap().shutdown(Duration::ZERO);
+                });
+            let Address::Tcp(addr) = server.local_addrs().unwrap()[0] else {
+                panic!("expected a tcp address");
+            };
+            let server = std::thread::spawn(move || server.event_loop());
+
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(bytes).unwrap();
+            server.join().unwrap().unwrap();
+            let mut reasons = reasons.lock().unwrap();
+            assert_eq!(reasons.len(), 1);
+            reasons.remove(0)
+        }
+
+        assert_eq!(disconnect_reason(&[1, 42]), "decode error: malformed packet: unknown enum variant 42");
+        assert_eq!(
+            disconnect_reason(&[0xE8, 0x07]),
+            "decode error: malformed frame: frame of 1000 bytes exceeds the maximum of 64 bytes",
+        );
+    }
+
+    #[test]
+    pub fn test_violation_policies() {
+        fn run(policy: ViolationPolicy) -> (bool, Vec<String>) {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let mut server: Server<ProtocolState, Packets> = Server::new();
+            let (violation_log, hello_log, disconnect_log) = (log.clone(), log.clone(), log.clone());
+            server
+                .with_violation_policy(policy)
+                .on_protocol_violation(move |_, violation| violation_log.lock().unwrap().push(format!("violation: {}", violation)))
+                .on::<packets::C2SHello>(move |_, name| hello_log.lock().unwrap().push(format!("hello: {}", name)))
+                .with_disconnect_event(move |_, reason| {
+                    if let DisconnectReason::ProtocolViolation(violation) = reason {
+                        disconnect_log.lock().unwrap().push(format!("disconnect: {}", violation));
+                    }
+                });
+            let mut loopback = Loopback::new(server).unwrap();
+
+            // The server connection stays in Login, where `ListPlayers` does not exist.
+            let client = loopback.connect(Client::new()).unwrap();
+            client.set_state(ProtocolState::Play);
+            client.send_packet(Packets::C2SListPlayers).unwrap();
+            client.set_state(ProtocolState::Login);
+            client.send_packet(Packets::C2SHello("alice".to_string())).unwrap();
+            loopback.pump();
+            let connected = loopback.is_connected(&client);
+            let log = log.lock().unwrap().clone();
+            (connected, log)
+        }
+
+        let (connected, log) = run(ViolationPolicy::Drop);
+        assert!(connected);
+        assert_eq!(log, ["violation: packet id 1 is unknown in the current state", "hello: alice"]);
+
+        assert_eq!(ViolationPolicy::default(), ViolationPolicy::Disconnect);
+        let (connected, log) = run(ViolationPolicy::default());
+        assert!(!connected);
+        assert_eq!(log, [
+            "violation: packet id 1 is unknown in the current state",
+            "disconnect: packet id 1 is unknown in the current state",
+        ]);
+    }
+
+    #[test]
+    pub fn test_unrouted_packets_reach_fallback() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let (route_log, fallback_log) = (log.clone(), log.clone());
+        server
+            .with_connection_event(|conn| conn.set_state(ProtocolState::Play))
+            .on::<packets::C2SMove>(move |_, (x, y)| route_log.lock().unwrap().push(format!("move: {} {}", x, y)))
+            .with_fallback_event(move |_, packet| fallback_log.lock().unwrap().push(format!("fallback: {:?}", packet)));
+        let mut loopback = Loopback::new(server).unwrap();
+
+        let client = loopback.connect(Client::new()).unwrap();
+        client.set_state(ProtocolState::Play);
+        client.send_packet(Packets::C2SMove(1, 2)).unwrap();
+        client.send_packet(Packets::C2SListPlayers).unwrap();
+        loopback.pump();
+        assert_eq!(*log.lock().unwrap(), ["move: 1 2", "fallback: C2SListPlayers"]);
+    }
+
+    #[test]
+    pub fn test_with_address_reports_bind_errors() {
+        let mut first: Server<ProtocolState, Packets> = Server::new();
+        let addrs = first.with_address("127.0.0.1:0").unwrap().local_addrs().unwrap();
+        assert_eq!(addrs.len(), 1);
+
+        let mut second: Server<ProtocolState, Packets> = Server::new();
+        let Address::Tcp(addr) = addrs[0] else {
+            panic!("expected a tcp address");
+        };
+        let err = second.with_address(addr).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(second.with_address("not a socket address").is_err());
+    }
+
+    #[test]
+    pub fn test_shutdown_sends_farewell_and_drains() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let server_log = log.clone();
+        server
+            .with_address("127.0.0.1:0")
+            .unwrap()
+            .on::<packets::C2SHello>(|conn, _| {
+                conn.set_state(ProtocolState::Play);
+                conn.server().unwrap().shutdown_with_farewell(Duration::from_secs(5), Packets::S2CChat("bye".to_string()));
+            })
+            .with_disconnect_event(move |_, reason| {
+                assert!(matches!(reason, DisconnectReason::Shutdown));
+                server_log.lock().unwrap().push("server: shutdown".to_string());
+            });
+        let Address::Tcp(addr) = server.local_addrs().unwrap()[0] else {
+            panic!("expected a tcp address");
+        };
+        let server = std::thread::spawn(move || server.event_loop());
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        let (chat_log, disconnect_log) = (log.clone(), log.clone());
+        client
+            .with_address(addr)
+            .on_connect(|crf| {
+                crf.send_packet(Packets::C2SHello("alice".to_string())).unwrap();
+                crf.set_state(ProtocolState::Play);
+            })
+            .on::<packets::S2CChat>(move |_, message| chat_log.lock().unwrap().push(format!("client <- {}", message)))
+            .on_disconnect(move |_, reason| disconnect_log.lock().unwrap().push(format!("client: {}", reason)));
+        client.event_loop().unwrap();
+        server.join().unwrap().unwrap();
+
+        let log = log.lock().unwrap();
+        assert!(log.contains(&"server: shutdown".to_string()));
+        let client_log: Vec<_> = log.iter().filter(|line| line.starts_with("client")).collect();
+        assert_eq!(client_log, ["client <- bye", "client: connection closed by peer"]);
+    }
+
+    #[test]
+    pub fn test_keepalive_over_sockets() {
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let (connect_log, disconnect_log) = (connections.clone(), log.clone());
+        server
+            .with_address("127.0.0.1:0")
+            .unwrap()
+            .with_keepalive(Duration::from_millis(20), Duration::from_millis(300))
+            .with_connection_event(move |conn| connect_log.lock().unwrap().push(conn))
+            .with_disconnect_event(move |_, reason| disconnect_log.lock().unwrap().push(reason.to_string()));
+        let Address::Tcp(addr) = server.local_addrs().unwrap()[0] else {
+            panic!("expected a tcp address");
+        };
+        let server = std::thread::spawn(move || server.event_loop());
+
+        // Both ends ping, so both ends learn the round trip time.
+        let client_ref = Arc::new(Mutex::new(None));
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        let connected = client_ref.clone();
+        client
+            .with_address(addr)
+            .with_keepalive(Duration::from_millis(20), Duration::from_secs(5))
+            .on_connect(move |crf| *connected.lock().unwrap() = Some(crf));
+        let client = std::thread::spawn(move || client.event_loop());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let client_rtt = client_ref.lock().unwrap().as_ref().and_then(|crf| crf.rtt());
+            let server_rtt = connections.lock().unwrap().first().and_then(|conn| conn.rtt());
+            if client_rtt.is_some() && server_rtt.is_some() {
+                break;
+            }
+            assert!(Instant::now() < deadline, "no round trip measured");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        client_ref.lock().unwrap().take().unwrap().disconnect();
+        client.join().unwrap().unwrap();
+
+        // A peer that answers three pings and then goes quiet gets exactly one more ping,
+        // which only happens if the heartbeat is rescheduled after each one, and is then
+        // dropped once the timeout passes.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut decoder = FrameDecoder::default();
+        let mut chunk = [0; 64];
+        let mut pings = 0;
+        loop {
+            let read = stream.read(&mut chunk).unwrap();
+            if read == 0 {
+                break;
+            }
+            decoder.push(&chunk[..read]);
+            while let Some(mut frame) = decoder.next_frame().unwrap() {
+                let FrameKind::Ping(nonce) = FrameKind::read(&mut frame).unwrap() else {
+                    panic!("expected a ping");
+                };
+                pings += 1;
+                if pings <= 3 {
+                    let mut pong = Vec::new();
+                    write_control_frame(&mut pong, FrameKind::Pong(nonce));
+                    stream.write_all(&pong).unwrap();
+                }
+            }
+        }
+        assert_eq!(pings, 4);
+
+        connections.lock().unwrap()[0].server().unwrap().shutdown(Duration::ZERO);
+        server.join().unwrap().unwrap();
+        assert_eq!(*log.lock().unwrap(), ["connection closed by peer", "connection timed out"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_unix_socket_transport() {
+        let path = std::env::temp_dir().join(format!("dragonet-server-{}.sock", std::process::id()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let expected = path.clone();
+        server
+            .with_unix_path(&path)
+            .unwrap()
+            .on::<packets::C2SHello>(move |conn, name| {
+                assert_eq!(name, "unix");
+                assert_eq!(conn.peer_addr(), Address::Unix(None));
+                assert_eq!(conn.local_addr(), Address::Unix(Some(expected.clone())));
+                conn.server().unwrap().shutdown(Duration::ZERO);
+            });
+        assert_eq!(server.local_addrs().unwrap(), [Address::Unix(Some(path.clone()))]);
+        let server = std::thread::spawn(move || server.event_loop());
+
+        let mut client: Client<ProtocolState, Packets> = Client::new();
+        client
+            .with_unix_path(&path)
+            .on_connect(|crf| crf.send_packet(Packets::C2SHello("unix".to_string())).unwrap());
+        client.event_loop().unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!path.exists());
+    }
+}
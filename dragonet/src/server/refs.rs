@@ -1,7 +1,13 @@
+use std::any::{Any, TypeId};
 use std::sync::{Arc, Mutex, MutexGuard};
-use crate::protocol::{PacketState, Protocol};
+use std::time::{Duration, Instant};
+use crate::disconnect::DisconnectReason;
+use crate::framing::FrameKind;
+use crate::protocol::{PacketState, Protocol, ProtocolViolation};
+use crate::request::RequestId;
 use crate::server::conn::ServerConnection;
-use crate::server::Server;
+use crate::server::{ConnectionId, Server, ShutdownRequest};
+use crate::transport::Address;
 
 pub struct ConnectionRef<S, T>
 where
@@ -16,15 +22,92 @@ where
     S: PacketState,
     T: Protocol<S>,
 {
+    pub fn id(&self) -> ConnectionId {
+        self.connection.lock().unwrap().id
+    }
+
+    pub fn peer_addr(&self) -> Address {
+        self.connection.lock().unwrap().peer_addr.clone()
+    }
+
+    pub fn local_addr(&self) -> Address {
+        self.connection.lock().unwrap().local_addr.clone()
+    }
+
     pub fn set_state(&self, state: S) {
         self.connection.lock().unwrap().state = Some(state);
     }
 
-    pub fn send_packet(&self, packet: T) {
-        self.connection.lock().unwrap().packet_queue.push(packet);
+    pub fn rtt(&self) -> Option<Duration> {
+        self.connection.lock().unwrap().heartbeat.rtt()
+    }
+
+    pub fn state(&self) -> S {
+        self.connection.lock().unwrap().current_state()
+    }
+
+    pub fn send_packet(&self, packet: T) -> Result<(), ProtocolViolation> {
+        self.connection.lock().unwrap().send_packet(packet)
+    }
+
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.connection.lock().unwrap().current_request
+    }
+
+    pub fn respond(&self, request: RequestId, packet: T) -> Result<(), ProtocolViolation> {
+        self.connection.lock().unwrap().respond(request, packet)
+    }
+
+    pub fn insert<A: Any + Send>(&self, value: A) -> Option<A> {
+        self.connection.lock().unwrap().attachments.insert(value)
+    }
+
+    pub fn get<A: Any + Send + Clone>(&self) -> Option<A> {
+        self.connection.lock().unwrap().attachments.get::<A>().cloned()
+    }
+
+    pub fn contains<A: Any + Send>(&self) -> bool {
+        self.connection.lock().unwrap().attachments.get::<A>().is_some()
+    }
+
+    pub fn with_mut<A: Any + Send, R>(&self, f: impl FnOnce(&mut A) -> R) -> Option<R> {
+        self.connection.lock().unwrap().attachments.get_mut::<A>().map(f)
+    }
+
+    pub fn remove<A: Any + Send>(&self) -> Option<A> {
+        self.connection.lock().unwrap().attachments.remove()
+    }
+
+    pub fn disconnect(&self, reason: impl Into<String>) {
+        self.connection.lock().unwrap().disconnect(DisconnectReason::Kicked(reason.into()));
+    }
+
+    pub fn shared_state<U: Any + Send + Sync>(&self) -> Option<Arc<U>> {
+        self.server()?.shared_state()
+    }
+
+    pub fn server(&self) -> Option<ServerRef<S, T>> {
+        let server = self.connection.lock().unwrap().server.upgrade()?;
+        Some(ServerRef { server })
     }
 }
 
+impl<S, T> PartialEq for ConnectionRef<S, T>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.connection, &other.connection)
+    }
+}
+
+impl<S, T> Eq for ConnectionRef<S, T>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{}
+
 impl<S, T> Clone for ConnectionRef<S, T>
 where
     S: PacketState,
@@ -66,15 +149,125 @@ impl<S: PacketState, T: Protocol<S>> ServerRef<S, T> {
         self.server.lock().unwrap()
     }
 
-    pub fn connections() -> Vec<ConnectionRef<S, T>> {
-        todo!()
+    pub fn shared_state<U: Any + Send + Sync>(&self) -> Option<Arc<U>> {
+        let state = self.lock().shared_state.get(&TypeId::of::<U>())?.clone();
+        state.downcast().ok()
+    }
+
+    pub fn shutdown(&self, grace: Duration) {
+        self.request_shutdown(grace, None);
+    }
+
+    pub fn shutdown_with_farewell(&self, grace: Duration, farewell: T) {
+        self.request_shutdown(grace, Some(farewell));
+    }
+
+    fn request_shutdown(&self, grace: Duration, farewell: Option<T>) {
+        let mut server = self.lock();
+        if server.shutdown.is_some() {
+            return;
+        }
+        server.shutdown = Some(ShutdownRequest { deadline: Instant::now() + grace, farewell });
+        if let Some(waker) = &server.waker {
+            let _ = waker.wake();
+        }
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionRef<S, T>> {
+        self.lock().connections.values()
+            .map(|connection| ConnectionRef { connection: connection.clone() })
+            .collect()
+    }
+
+    pub fn broadcast(&self, packet: T) {
+        self.broadcast_where(packet, |_| true);
+    }
+
+    pub fn broadcast_except(&self, except: ConnectionId, packet: T) {
+        self.broadcast_where(packet, |connection| connection.id() != except);
     }
 
-    pub fn broadcast(packet: T) {
-        todo!()
+    pub fn broadcast_where<F>(&self, packet: T, filter: F)
+    where
+        F: Fn(&ConnectionRef<S, T>) -> bool,
+    {
+        let frame = ServerConnection::encode_frame(FrameKind::Packet, &packet);
+        for connection in self.connections() {
+            if !filter(&connection) {
+                continue;
+            }
+            let mut connection = connection.connection.lock().unwrap();
+            if connection.accepts(&packet).is_ok() {
+                connection.queue_frame(frame.clone());
+            }
+        }
     }
 
     pub(crate) fn tmp_lock<R>(&self, f: fn(MutexGuard<'_, Server<S, T>>) -> R) -> R {
         f(self.lock())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use crate::client::Client;
+    use crate::protocol::tests::{packets, Packets, ProtocolState};
+    use crate::server::Server;
+    use crate::testing::Loopback;
+
+    #[test]
+    pub fn test_broadcast_filters() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        server.with_connection_event(|conn| conn.set_state(ProtocolState::Play));
+        let mut loopback = Loopback::new(server).unwrap();
+        for index in 0..3 {
+            let mut client: Client<ProtocolState, Packets> = Client::new();
+            let log = log.clone();
+            client.on::<packets::S2CChat>(move |_, message| {
+                log.lock().unwrap().push(format!("{} <- {}", index, message))
+            });
+            loopback.connect(client).unwrap().set_state(ProtocolState::Play);
+        }
+        loopback.pump();
+
+        let server = loopback.server();
+        let mut connections = server.connections();
+        connections.sort_by_key(|connection| connection.id());
+        server.broadcast_except(connections[0].id(), Packets::S2CChat("except".to_string()));
+        loopback.pump();
+        assert_eq!(std::mem::take(&mut *log.lock().unwrap()), ["1 <- except", "2 <- except"]);
+
+        let only = connections[2].id();
+        server.broadcast_where(Packets::S2CChat("where".to_string()), |connection| connection.id() == only);
+        loopback.pump();
+        assert_eq!(*log.lock().unwrap(), ["2 <- where"]);
+    }
+
+    #[test]
+    pub fn test_shared_state() {
+        struct Unregistered;
+
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        server
+            .with_shared_state(AtomicUsize::new(0))
+            .on::<packets::C2SHello>(|conn, _| {
+                assert!(conn.shared_state::<Unregistered>().is_none());
+                let via_server = conn.server().unwrap().shared_state::<AtomicUsize>().unwrap();
+                via_server.fetch_add(1, Ordering::SeqCst);
+                conn.shared_state::<AtomicUsize>().unwrap().fetch_add(10, Ordering::SeqCst);
+            });
+        let mut loopback = Loopback::new(server).unwrap();
+        for _ in 0..2 {
+            let client = loopback.connect(Client::new()).unwrap();
+            client.send_packet(Packets::C2SHello("alice".to_string())).unwrap();
+        }
+        loopback.pump();
+
+        let server = loopback.server();
+        assert_eq!(server.shared_state::<AtomicUsize>().unwrap().load(Ordering::SeqCst), 22);
+        assert!(server.shared_state::<Unregistered>().is_none());
+    }
+}
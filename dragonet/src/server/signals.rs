@@ -0,0 +1,155 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use mio::Waker;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use signal_hook::iterator::{Handle, Signals};
+use signal_hook::SigId;
+
+const SIGNALS: [i32; 2] = [SIGINT, SIGTERM];
+
+/// While set, SIGINT and SIGTERM get their default action. Cleared while a server is waiting
+/// for its first signal, and set again by that signal or when the server stops listening.
+static DEFAULT_ACTION: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+fn default_action() -> io::Result<Arc<AtomicBool>> {
+    let mut default_action = DEFAULT_ACTION.lock().unwrap();
+    if let Some(condition) = &*default_action {
+        return Ok(condition.clone());
+    }
+
+    // Registered once and never removed: signal-hook cannot put the original disposition
+    // back, so this is what keeps the signals fatal once no server is handling them.
+    let condition = Arc::new(AtomicBool::new(true));
+    for signal in SIGNALS {
+        flag::register_conditional_default(signal, condition.clone())?;
+    }
+    *default_action = Some(condition.clone());
+    Ok(condition)
+}
+
+fn unregister(flags: Vec<SigId>) {
+    for id in flags {
+        signal_hook::low_level::unregister(id);
+    }
+}
+
+pub(crate) struct SignalGuard {
+    handle: Handle,
+    flags: Vec<SigId>,
+    default_action: Arc<AtomicBool>,
+    triggered: Arc<AtomicBool>,
+}
+
+impl SignalGuard {
+    /// Turns the first SIGINT or SIGTERM into a wakeup of the event loop. A second signal
+    /// gets the default action, so an impatient Ctrl-C still stops a slow shutdown.
+    pub(crate) fn install(waker: Arc<Waker>) -> io::Result<SignalGuard> {
+        let default_action = default_action()?;
+        let mut flags = Vec::with_capacity(SIGNALS.len());
+        for signal in SIGNALS {
+            // Runs after the conditional default, so only signals after the first are fatal.
+            match flag::register(signal, default_action.clone()) {
+                Ok(id) => flags.push(id),
+                Err(err) => {
+                    unregister(flags);
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut signals = match Signals::new(SIGNALS) {
+            Ok(signals) => signals,
+            Err(err) => {
+                unregister(flags);
+                return Err(err);
+            }
+        };
+        let handle = signals.handle();
+        let triggered = Arc::new(AtomicBool::new(false));
+        let flag = triggered.clone();
+        std::thread::Builder::new()
+            .name("dragonet-signals".to_string())
+            .spawn(move || {
+                for _ in signals.forever() {
+                    flag.store(true, Ordering::SeqCst);
+                    let _ = waker.wake();
+                }
+            })?;
+        default_action.store(false, Ordering::SeqCst);
+        Ok(SignalGuard { handle, flags, default_action, triggered })
+    }
+
+    pub(crate) fn triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        self.default_action.store(true, Ordering::SeqCst);
+        self.handle.close();
+        unregister(std::mem::take(&mut self.flags));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use mio::{Events, Poll, Token, Waker};
+    use signal_hook::consts::SIGTERM;
+    use crate::server::signals::SignalGuard;
+
+    const CHILD_MODE: &str = "DRAGONET_SIGNAL_TEST";
+
+    /// Only does anything when started by `test_signal_handling`, which runs it in its own
+    /// process so the signals raised here never reach the rest of the test binary.
+    #[test]
+    pub fn test_signal_child() {
+        let Ok(mode) = std::env::var(CHILD_MODE) else {
+            return;
+        };
+
+        let mut poll = Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), Token(0)).unwrap());
+        let guard = SignalGuard::install(waker).unwrap();
+        assert!(!guard.triggered());
+
+        signal_hook::low_level::raise(SIGTERM).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Events::with_capacity(1);
+        while !guard.triggered() {
+            assert!(Instant::now() < deadline, "signal was not delivered");
+            poll.poll(&mut events, Some(Duration::from_millis(100))).unwrap();
+        }
+
+        if mode == "after-drop" {
+            drop(guard);
+        }
+        signal_hook::low_level::raise(SIGTERM).unwrap();
+        std::thread::sleep(Duration::from_secs(5));
+        panic!("second signal did not terminate the process");
+    }
+
+    #[test]
+    pub fn test_signal_handling() {
+        for mode in ["repeated", "after-drop"] {
+            let output = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "server::signals::tests::test_signal_child", "--nocapture"])
+                .env(CHILD_MODE, mode)
+                .output()
+                .unwrap();
+            assert_eq!(
+                output.status.signal(),
+                Some(SIGTERM),
+                "{}: {}",
+                mode,
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+use mio::{Poll, Token, Waker};
+use crate::client::{Client, ClientRef, ClientSession};
+use crate::protocol::{PacketState, Protocol};
+use crate::server::{Server, ServerRef};
+use crate::transport::{Address, MemoryStream, Stream};
+
+const MAX_ROUNDS: usize = 10_000;
+
+struct LoopbackClient<S, T>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    client: ClientRef<S, T>,
+    session: ClientSession,
+}
+
+/// Runs a `Server` and any number of `Client`s over in-memory streams on the calling
+/// thread. Nothing moves until `pump` is called, and connections are always serviced in
+/// the same order, so tests see the same interleaving on every run.
+pub struct Loopback<S, T>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    server: ServerRef<S, T>,
+    poll: Poll,
+    waker: Arc<Waker>,
+    draining: Option<Instant>,
+    stopped: bool,
+    clients: Vec<LoopbackClient<S, T>>,
+    streams: Vec<MemoryStream>,
+}
+
+impl<S, T> Loopback<S, T>
+where
+    S: PacketState,
+    T: Protocol<S>,
+{
+    /// Runs the server's startup events. Addresses configured on `server` are ignored.
+    pub fn new(server: Server<S, T>) -> io::Result<Loopback<S, T>> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), Token(0))?);
+        let server = server.start(waker.clone());
+        Ok(Loopback {
+            server,
+            poll,
+            waker,
+            draining: None,
+            stopped: false,
+            clients: Vec::new(),
+            streams: Vec::new(),
+        })
+    }
+
+    pub fn server(&self) -> ServerRef<S, T> {
+        self.server.clone()
+    }
+
+    /// Connects `client` to the server, firing the server's connection events and then the
+    /// client's `on_connect`. Reconnect policies are not applied to loopback clients.
+    pub fn connect(&mut self, client: Client<S, T>) -> io::Result<ClientRef<S, T>> {
+        if self.stopped || self.draining.is_some() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "server is shutting down"));
+        }
+
+        let pair = self.streams.len();
+        let (client_end, server_end) = MemoryStream::pair(pair);
+        self.streams.push(client_end.clone());
+        Server::add_connection(
+            &self.server,
+            Stream::Memory(server_end),
+            Address::Memory(pair),
+            Address::Memory(pair),
+            self.poll.registry(),
+            &self.waker,
+        )?;
+
+        let client = ClientRef::new(client);
+        let session = Client::begin_session(&client, Stream::Memory(client_end), self.waker.clone(), None);
+        self.clients.push(LoopbackClient { client: client.clone(), session });
+        Ok(client)
+    }
+
+    pub fn is_connected(&self, client: &ClientRef<S, T>) -> bool {
+        self.clients.iter().any(|connected| connected.client.same_client(client))
+    }
+
+    /// Steps the server and every client until a full round moves no bytes and closes no
+    /// streams. Returns the number of rounds taken.
+    pub fn pump(&mut self) -> usize {
+        for round in 1..=MAX_ROUNDS {
+            let before = self.activity();
+            self.step();
+            if self.activity() == before {
+                return round;
+            }
+        }
+        panic!("loopback did not settle after {} rounds", MAX_ROUNDS);
+    }
+
+    fn step(&mut self) {
+        if !self.stopped {
+            let mut connections = self.server.connections();
+            connections.sort_by_key(|connection| connection.id());
+            for connection in connections {
+                Server::read_connection(&self.server, self.poll.registry(), connection.id());
+            }
+            self.stopped = Server::tick(&self.server, self.poll.registry(), &mut Vec::new(), &mut self.draining);
+        }
+
+        let mut index = 0;
+        while index < self.clients.len() {
+            let connected = &mut self.clients[index];
+            match Client::step(&connected.client, &mut connected.session, true) {
+                Some(reason) => {
+                    let connected = self.clients.remove(index);
+                    Client::end_session(&connected.client, connected.session, &reason);
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    fn activity(&self) -> u64 {
+        self.streams.iter().map(MemoryStream::activity).sum()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::client::Client;
+    use crate::protocol::tests::{packets, Packets, ProtocolState};
+    use crate::server::Server;
+    use crate::testing::Loopback;
+
+    #[test]
+    pub fn test_loopback_state_transitions_and_broadcasts() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        let server_log = log.clone();
+        server
+            .on::<packets::C2SHello>(|conn, name| {
+                conn.set_state(ProtocolState::Play);
+                conn.server().unwrap().broadcast(Packets::S2CChat(format!("{} joined", name)));
+            })
+            .on::<packets::C2SMove>(|conn, _| conn.disconnect("no moving"))
+            .with_disconnect_event(move |conn, reason| {
+                server_log.lock().unwrap().push(format!("server: {} {}", conn.peer_addr(), reason))
+            });
+        let mut loopback = Loopback::new(server).unwrap();
+
+        let mut clients = Vec::new();
+        for name in ["alice", "bob"] {
+            let mut client: Client<ProtocolState, Packets> = Client::new();
+            let (chat_log, disconnect_log) = (log.clone(), log.clone());
+            client
+                .on::<packets::S2CChat>(move |_, message| {
+                    chat_log.lock().unwrap().push(format!("{} <- {}", name, message))
+                })
+                .on_disconnect(move |_, reason| {
+                    disconnect_log.lock().unwrap().push(format!("{}: {}", name, reason))
+                });
+            let client = loopback.connect(client).unwrap();
+            client.send_packet(Packets::C2SHello(name.to_string())).unwrap();
+            client.set_state(ProtocolState::Play);
+            clients.push(client);
+        }
+
+        loopback.pump();
+        assert_eq!(std::mem::take(&mut *log.lock().unwrap()), [
+            "alice <- alice joined",
+            "alice <- bob joined",
+            "bob <- bob joined",
+        ]);
+
+        clients[0].send_packet(Packets::C2SMove(1, 2)).unwrap();
+        loopback.pump();
+        assert!(!loopback.is_connected(&clients[0]));
+        assert!(loopback.is_connected(&clients[1]));
+        assert_eq!(loopback.server().connections().len(), 1);
+        assert_eq!(*log.lock().unwrap(), [
+            "server: memory:0 kicked: no moving",
+            "alice: connection closed by peer",
+        ]);
+
+        log.lock().unwrap().clear();
+        loopback.server().shutdown(Duration::from_secs(1));
+        loopback.pump();
+        assert!(!loopback.is_connected(&clients[1]));
+        assert!(loopback.connect(Client::new()).is_err());
+        assert_eq!(*log.lock().unwrap(), [
+            "server: memory:1 server shutting down",
+            "bob: connection closed by peer",
+        ]);
+    }
+
+    #[test]
+    pub fn test_loopback_requests() {
+        let mut server: Server<ProtocolState, Packets> = Server::new();
+        server
+            .with_connection_event(|conn| conn.set_state(ProtocolState::Play))
+            .on_request::<packets::C2SListPlayers>(|conn, request, ()| {
+                conn.respond(request, Packets::S2CPlayerList(vec!["alice".to_string()])).unwrap();
+            });
+        let mut loopback = Loopback::new(server).unwrap();
+
+        let client = loopback.connect(Client::new()).unwrap();
+        client.set_state(ProtocolState::Play);
+        let pending = client.send_request::<packets::C2SListPlayers>(()).unwrap();
+        loopback.pump();
+        assert_eq!(pending.wait(Duration::ZERO), Ok(vec!["alice".to_string()]));
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Tcp(SocketAddr),
+    /// `None` for unnamed sockets, which is what most unix clients connect from.
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+    /// Both ends of an in-memory pair report the pair's number.
+    Memory(usize),
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Address::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Address::Unix(None) => write!(f, "unix:(unnamed)"),
+            Address::Memory(pair) => write!(f, "memory:{}", pair),
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(value: SocketAddr) -> Self {
+        Address::Tcp(value)
+    }
+}
+
+#[cfg(unix)]
+impl From<std::os::unix::net::SocketAddr> for Address {
+    fn from(value: std::os::unix::net::SocketAddr) -> Self {
+        Address::Unix(value.as_pathname().map(Path::to_path_buf))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(mio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(mio::net::UnixStream),
+    Memory(MemoryStream),
+}
+
+impl Stream {
+    pub(crate) fn connect(addr: &Address, timeout: Duration) -> io::Result<Stream> {
+        match addr {
+            Address::Tcp(addr) => {
+                let stream = std::net::TcpStream::connect_timeout(addr, timeout)?;
+                stream.set_nonblocking(true)?;
+                Ok(Stream::Tcp(mio::net::TcpStream::from_std(stream)))
+            }
+            #[cfg(unix)]
+            Address::Unix(Some(path)) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_nonblocking(true)?;
+                Ok(Stream::Unix(mio::net::UnixStream::from_std(stream)))
+            }
+            #[cfg(unix)]
+            Address::Unix(None) => Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot connect to an unnamed unix socket")),
+            Address::Memory(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "memory streams are created in pairs")),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr().map(Address::from),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.local_addr().map(Address::from),
+            Stream::Memory(stream) => Ok(Address::Memory(stream.pair)),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+            Stream::Memory(stream) => {
+                stream.shutdown(how);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Memory(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Memory(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Memory(_) => Ok(()),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.register(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.register(registry, token, interests),
+            Stream::Memory(_) => Ok(()),
+        }
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.reregister(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.reregister(registry, token, interests),
+            Stream::Memory(_) => Ok(()),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.deregister(registry),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.deregister(registry),
+            Stream::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    buffer: VecDeque<u8>,
+    closed: bool,
+    activity: u64,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.activity += 1;
+        }
+    }
+}
+
+/// One end of an in-memory connection. Memory streams are never registered with mio,
+/// so whoever owns them has to poll them.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryStream {
+    pair: usize,
+    inbound: Arc<Mutex<Pipe>>,
+    outbound: Arc<Mutex<Pipe>>,
+}
+
+impl MemoryStream {
+    pub(crate) fn pair(pair: usize) -> (MemoryStream, MemoryStream) {
+        let (left, right) = (Arc::new(Mutex::new(Pipe::default())), Arc::new(Mutex::new(Pipe::default())));
+        (
+            MemoryStream { pair, inbound: left.clone(), outbound: right.clone() },
+            MemoryStream { pair, inbound: right, outbound: left },
+        )
+    }
+
+    /// Grows whenever bytes are written or either direction is closed.
+    pub(crate) fn activity(&self) -> u64 {
+        self.inbound.lock().unwrap().activity + self.outbound.lock().unwrap().activity
+    }
+
+    fn shutdown(&self, how: Shutdown) {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.inbound.lock().unwrap().close();
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.outbound.lock().unwrap().close();
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.inbound.lock().unwrap();
+        match (pipe.buffer.is_empty(), pipe.closed) {
+            (true, true) => Ok(0),
+            (true, false) => Err(io::ErrorKind::WouldBlock.into()),
+            (false, _) => pipe.buffer.read(buf),
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.outbound.lock().unwrap();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.buffer.extend(buf);
+        pipe.activity += 1;
+        Ok(buf.len())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(mio::net::TcpListener),
+    /// Keeps the bound path so the socket file can be removed once the listener closes.
+    #[cfg(unix)]
+    Unix(mio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub(crate) fn bind_tcp(addr: SocketAddr) -> io::Result<Listener> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Tcp(mio::net::TcpListener::from_std(listener)))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(path: &Path) -> io::Result<Listener> {
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Unix(mio::net::UnixListener::from_std(listener), path.to_path_buf()))
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<(Stream, Address)> {
+        match self {
+            Listener::Tcp(listener) => listener.accept()
+                .map(|(stream, addr)| (Stream::Tcp(stream), addr.into())),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept()
+                .map(|(stream, addr)| (Stream::Unix(stream), addr.into())),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::from),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.local_addr().map(Address::from),
+        }
+    }
+}
+
+impl Source for Listener {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.register(registry, token, interests),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.reregister(registry, token, interests),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.deregister(registry),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.deregister(registry),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io;
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::time::Duration;
+    use crate::transport::{Address, Listener, MemoryStream, Stream};
+
+    #[test]
+    pub fn test_tcp_listener_accepts() {
+        let listener = Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = Stream::connect(&addr, Duration::from_secs(1)).unwrap();
+
+        let (_, peer) = loop {
+            match listener.accept() {
+                Ok(accepted) => break accepted,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) => panic!("{}", err),
+            }
+        };
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert!(matches!(addr, Address::Tcp(_)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_unix_listener_removes_socket_file() {
+        let path = std::env::temp_dir().join(format!("dragonet-test-{}.sock", std::process::id()));
+        let listener = Listener::bind_unix(&path).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), Address::Unix(Some(path.clone())));
+        assert_eq!(Address::Unix(None).to_string(), "unix:(unnamed)");
+
+        let client = Stream::connect(&Address::Unix(Some(path.clone())), Duration::from_secs(1)).unwrap();
+        assert_eq!(client.local_addr().unwrap(), Address::Unix(None));
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    pub fn test_memory_stream_pair() {
+        let (left, right) = MemoryStream::pair(3);
+        let (mut left, mut right) = (Stream::Memory(left), Stream::Memory(right));
+        let mut buf = [0; 8];
+        assert_eq!(right.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        left.write_all(b"hello").unwrap();
+        assert_eq!(right.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(right.local_addr().unwrap(), Address::Memory(3));
+
+        left.shutdown(Shutdown::Both).unwrap();
+        assert_eq!(right.read(&mut buf).unwrap(), 0);
+        assert_eq!(right.write(b"late").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
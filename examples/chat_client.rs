@@ -1,34 +1,53 @@
+use std::io;
 use std::io::stdin;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Once;
+use std::time::Duration;
 use dragonet::client::Client;
-use dragonet::protocol::PacketState;
+use dragonet::reconnect::ReconnectPolicy;
 use dragonet_macros::client;
-use crate::chat_protocol::{Packets, ProtocolState};
+use crate::chat_protocol::{packets, Packets, ProtocolState};
 
 pub mod chat_protocol;
 
 #[client]
-pub fn client_provider(client: &mut Client<ProtocolState, Packets>) -> &mut Client<ProtocolState, Packets> {
-    client
-        .with_address(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 2000))
+pub fn client_provider(client: &mut Client<ProtocolState, Packets>) -> io::Result<&mut Client<ProtocolState, Packets>> {
+    Ok(client
+        .with_address("localhost:2000")
+        .with_connect_timeout(Duration::from_secs(5))
+        .on_connect_error(|_crf, err| eprintln!("could not connect: {}", err))
+        .with_reconnect(ReconnectPolicy {
+            max_attempts: Some(10),
+            jitter: 0.2,
+            ..ReconnectPolicy::new(Duration::from_millis(500), Duration::from_secs(10))
+        })
+        .on_reconnecting(|_crf, attempt, delay| eprintln!("reconnecting (attempt {}) in {:?}", attempt, delay))
+        .on_reconnected(|_crf, attempts| eprintln!("reconnected after {} attempt(s)", attempts))
         .on_connect(|crf| {
-            crf.send_packet(Packets::ServerboundChatMessage("I connected!".to_string()));
-            std::thread::spawn(move || {
-                crf.set_state(ProtocolState::Chat);
-                loop {
-                    let mut line = String::new();
-                    stdin().read_line(&mut line).unwrap();
-                    println!("{}", line);
-                    crf.send_packet(Packets::ServerboundChatMessage(line));
-                }
+            crf.send_packet(Packets::C2SChatMessage("I connected!".to_string())).unwrap();
+            static STDIN: Once = Once::new();
+            STDIN.call_once(|| {
+                std::thread::spawn(move || {
+                    crf.set_state(ProtocolState::Chat);
+                    loop {
+                        let mut line = String::new();
+                        if stdin().read_line(&mut line).unwrap() == 0 {
+                            crf.disconnect();
+                            break;
+                        }
+                        if line.trim() == "/who" {
+                            match crf.request::<packets::C2SListUsers>((), Duration::from_secs(5)) {
+                                Ok(users) => println!("online: {}", users.join(", ")),
+                                Err(err) => println!("/who failed: {}", err),
+                            }
+                            continue;
+                        }
+                        println!("{}", line);
+                        crf.send_packet(Packets::C2SChatMessage(line)).unwrap();
+                    }
+                });
             });
         })
-        .with_packet_event(|crf, packet| {
-            match packet {
-                Packets::ClientboundChatMessage(message) => {
-                    println!("> {}", message)
-                }
-                _ => panic!("got serverboudn packet {:?}", packet)
-            }
-        })
-}
\ No newline at end of file
+        .on::<packets::S2CChatMessage>(|_crf, message| {
+            println!("> {}", message)
+        }))
+}
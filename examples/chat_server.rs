@@ -1,24 +1,63 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use dragonet::server::Server;
 use dragonet_macros::server;
-use crate::chat_protocol::{Packets, ProtocolState};
+use crate::chat_protocol::{packets, Packets, ProtocolState};
 
 pub mod chat_protocol;
 
+#[derive(Clone)]
+struct Nickname(String);
+
+#[derive(Default)]
+struct ChatStats {
+    messages: AtomicUsize,
+}
+
 #[server]
-pub fn server_provider(server: &mut Server<ProtocolState, Packets>) -> &mut Server<ProtocolState, Packets> {
+pub fn server_provider(server: &mut Server<ProtocolState, Packets>) -> io::Result<&mut Server<ProtocolState, Packets>> {
     server_provider_impl(server)
 }
 
-pub fn server_provider_impl(server: &mut Server<ProtocolState, Packets>) -> &mut Server<ProtocolState, Packets> {
+pub fn server_provider_impl(server: &mut Server<ProtocolState, Packets>) -> io::Result<&mut Server<ProtocolState, Packets>> {
     server
-        .with_address(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 2000))
+        .with_address("localhost:2000")?
+        .with_keepalive(Duration::from_secs(1), Duration::from_secs(3));
+    #[cfg(unix)]
+    server.with_shutdown_signals(Duration::from_secs(2));
+    Ok(server
+        .with_shared_state(ChatStats::default())
         .with_connection_event(|conn| {
-            println!("Connected!");
+            println!("Connected: {} from {}", conn.id(), conn.peer_addr());
+            conn.insert(Nickname(format!("user{}", conn.id().get())));
             conn.set_state(ProtocolState::Chat);
-            conn.send_packet(Packets::ClientboundChatMessage("You connected! Hi!".to_string()))
+            conn.send_packet(Packets::S2CChatMessage("You connected! Hi!".to_string())).unwrap();
         })
-        .with_packet_event(|conn, packet| {
-            conn.send_packet(Packets::ClientboundChatMessage("Recv".to_string()));
+        .on::<packets::C2SChatMessage>(|conn, message| {
+            if let Some(stats) = conn.shared_state::<ChatStats>() {
+                stats.messages.fetch_add(1, Ordering::Relaxed);
+            }
+            let nickname = conn.get::<Nickname>().map(|nickname| nickname.0).unwrap_or_default();
+            if let Some(server) = conn.server() {
+                server.broadcast_except(conn.id(), Packets::S2CChatMessage(format!("<{}> {}", nickname, message)));
+            }
         })
-}
\ No newline at end of file
+        .on_request::<packets::C2SListUsers>(|conn, request, ()| {
+            let Some(server) = conn.server() else {
+                return;
+            };
+            let users = server.connections().iter()
+                .filter_map(|connection| connection.get::<Nickname>())
+                .map(|nickname| nickname.0)
+                .collect();
+            conn.respond(request, Packets::S2CUserList(users)).unwrap();
+        })
+        .with_disconnect_event(|conn, reason| {
+            let messages = conn.shared_state::<ChatStats>()
+                .map(|stats| stats.messages.load(Ordering::Relaxed))
+                .unwrap_or_default();
+            let rtt = conn.rtt().map(|rtt| format!("{:?}", rtt)).unwrap_or_else(|| "unknown".to_string());
+            println!("Disconnected: {} ({}), rtt {}, {} messages relayed so far", conn.id(), reason, rtt, messages);
+        }))
+}